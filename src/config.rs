@@ -1,15 +1,30 @@
-use std::{fs::{self, File}, io::BufReader, path::PathBuf};
+use std::{fs::{self, File}, io::BufReader, path::PathBuf, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct Config {
     port: u16,
-    data_directory: PathBuf
+    data_directory: PathBuf,
+    // both must be set to use a custom certificate, otherwise tls_self_signed generates one in the config directory
+    #[serde(default)]
+    tls_cert: Option<PathBuf>,
+    #[serde(default)]
+    tls_key: Option<PathBuf>,
+    #[serde(default)]
+    tls_self_signed: bool,
+    // seconds a ticket may stay inactive before it is cancelled
+    #[serde(default = "Config::default_ticket_ttl")]
+    ticket_ttl: u64,
+    // request body limits in bytes, file_limit applies to each uploaded file, including the whole size of chunked ones
+    #[serde(default = "Config::default_file_limit")]
+    file_limit: u64,
+    #[serde(default = "Config::default_json_limit")]
+    json_limit: u64
 }
 
 impl Config {
-    fn config_path() -> PathBuf {
+    pub fn config_path() -> PathBuf {
         dirs::config_dir().expect("Failed to get config dir").join("SaveSyncd")
     }
 
@@ -17,8 +32,26 @@ impl Config {
         Config::config_path().join("config.json")
     }
 
+    fn default_ticket_ttl() -> u64 { 1800 }
+    fn default_file_limit() -> u64 { 100 * 1024 * 1024 }
+    fn default_json_limit() -> u64 { 8 * 1024 * 1024 }
+
+    // the defaults, keeping data in data_directory
+    pub fn new(data_directory: PathBuf) -> Self {
+        Config {
+            port: 8000,
+            data_directory,
+            tls_cert: None,
+            tls_key: None,
+            tls_self_signed: false,
+            ticket_ttl: Config::default_ticket_ttl(),
+            file_limit: Config::default_file_limit(),
+            json_limit: Config::default_json_limit()
+        }
+    }
+
     pub fn load() -> Self {
-        let config = Config { port: 8000, data_directory: dirs::data_dir().expect("Failed to get data dir").join("SaveSyncd") };
+        let config = Config::new(dirs::data_dir().expect("Failed to get data dir").join("SaveSyncd"));
         let path = Config::config_file();
 
         if !fs::exists(path.clone()).unwrap_or(false) {
@@ -36,15 +69,19 @@ impl Config {
     }
 
     pub fn save(&self) {
-        if !fs::exists(Config::config_path()).unwrap_or(false) {
-            if fs::create_dir_all(Config::config_path()).is_err() {
-                return;
-            }
+        if !fs::exists(Config::config_path()).unwrap_or(false) && fs::create_dir_all(Config::config_path()).is_err() {
+            return;
         }
 
         fs::write(Config::config_file(), serde_json::to_string_pretty(self).expect("Failed to stringify config")).expect("Failed to write config");
     }
 
-    pub fn port(&self) -> u16 { return self.port }
-    pub fn data_directory(&self) -> PathBuf { return self.data_directory.clone() }
+    pub fn port(&self) -> u16 { self.port }
+    pub fn data_directory(&self) -> PathBuf { self.data_directory.clone() }
+    pub fn tls_cert(&self) -> Option<PathBuf> { self.tls_cert.clone() }
+    pub fn tls_key(&self) -> Option<PathBuf> { self.tls_key.clone() }
+    pub fn tls_self_signed(&self) -> bool { self.tls_self_signed }
+    pub fn ticket_ttl(&self) -> Duration { Duration::from_secs(self.ticket_ttl) }
+    pub fn file_limit(&self) -> u64 { self.file_limit }
+    pub fn json_limit(&self) -> u64 { self.json_limit }
 }
\ No newline at end of file
@@ -0,0 +1,167 @@
+use std::{fs::{self, File}, io::BufReader, path::PathBuf, sync::{Arc, Mutex}, time::{SystemTime, UNIX_EPOCH}};
+
+use rocket::{State, http::Status, request::{FromRequest, Outcome, Request}};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::Config;
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+pub struct Device {
+    pub name: String,
+    pub token: String,
+    pub created: u64
+}
+
+// devices are kept in <config dir>/devices.json, the file is reloaded when it changes on disk
+// so devices added or revoked from the command line apply to a running daemon
+#[derive(Debug, Default)]
+pub struct DeviceStore {
+    devices: Vec<Device>,
+    modified: Option<SystemTime>
+}
+
+pub type Devices = Arc<Mutex<DeviceStore>>;
+
+impl DeviceStore {
+    fn devices_file() -> PathBuf {
+        Config::config_path().join("devices.json")
+    }
+
+    fn file_modified() -> Option<SystemTime> {
+        fs::metadata(DeviceStore::devices_file()).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    pub fn load() -> Self {
+        let mut store = DeviceStore::default();
+        store.reload();
+
+        store
+    }
+
+    pub fn reload(&mut self) {
+        self.modified = DeviceStore::file_modified();
+        self.devices = File::open(DeviceStore::devices_file())
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default();
+    }
+
+    fn reload_if_changed(&mut self) {
+        if DeviceStore::file_modified() != self.modified {
+            self.reload();
+        }
+    }
+
+    pub fn save(&mut self) -> std::io::Result<()> {
+        fs::create_dir_all(Config::config_path())?;
+        fs::write(DeviceStore::devices_file(), serde_json::to_string_pretty(&self.devices).map_err(std::io::Error::other)?)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(DeviceStore::devices_file(), fs::Permissions::from_mode(0o600))?;
+        }
+
+        self.modified = DeviceStore::file_modified();
+        Ok(())
+    }
+
+    pub fn devices(&mut self) -> &[Device] {
+        self.reload_if_changed();
+        &self.devices
+    }
+
+    // returns None if a device with the same name already exists
+    pub fn add(&mut self, name: &str) -> std::io::Result<Option<Device>> {
+        self.reload_if_changed();
+        if self.devices.iter().any(|device| device.name == name) {
+            return Ok(None)
+        }
+
+        let device = Device {
+            name: name.to_string(),
+            token: format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple()),
+            created: SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+        };
+
+        self.devices.push(device.clone());
+        self.save()?;
+
+        Ok(Some(device))
+    }
+
+    // returns false if no device had the name
+    pub fn revoke(&mut self, name: &str) -> std::io::Result<bool> {
+        self.reload_if_changed();
+
+        let count = self.devices.len();
+        self.devices.retain(|device| device.name != name);
+        if self.devices.len() == count {
+            return Ok(false)
+        }
+
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn find(&mut self, token: &str) -> Option<Device> {
+        self.reload_if_changed();
+        self.devices.iter().find(|device| device.token == token).cloned()
+    }
+}
+
+// request guard for every authenticated route, expects "Authorization: Bearer <token>"
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Device {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(token) = request.headers().get_one("Authorization").and_then(|header| header.strip_prefix("Bearer ")) else {
+            return Outcome::Error((Status::Unauthorized, ()))
+        };
+
+        let Outcome::Success(devices) = request.guard::<&State<Devices>>().await else {
+            return Outcome::Error((Status::InternalServerError, ()))
+        };
+
+        let Ok(mut store) = devices.lock() else {
+            return Outcome::Error((Status::InternalServerError, ()))
+        };
+
+        match store.find(token.trim()) {
+            Some(device) => Outcome::Success(device),
+            None => Outcome::Error((Status::Unauthorized, ()))
+        }
+    }
+}
+
+// handles `SaveSyncd device <add|revoke|list> [name]`, returns false if the arguments weren't a device command
+pub fn run_command(args: &[String]) -> bool {
+    let [command, rest @ ..] = args else { return false };
+    if command != "device" {
+        return false
+    }
+
+    let mut store = DeviceStore::load();
+    match (rest.first().map(String::as_str), rest.get(1)) {
+        (Some("add"), Some(name)) => match store.add(name) {
+            Ok(Some(device)) => println!("Added device \"{}\", token: {}", device.name, device.token),
+            Ok(None) => println!("A device named \"{name}\" already exists"),
+            Err(err) => println!("Failed to save devices: {err}")
+        },
+        (Some("revoke"), Some(name)) => match store.revoke(name) {
+            Ok(true) => println!("Revoked device \"{name}\""),
+            Ok(false) => println!("No device named \"{name}\""),
+            Err(err) => println!("Failed to save devices: {err}")
+        },
+        (Some("list"), None) => {
+            for device in store.devices() {
+                println!("{}", device.name);
+            }
+        },
+        _ => println!("Usage: SaveSyncd device <add|revoke> <name>\n       SaveSyncd device list")
+    }
+
+    true
+}
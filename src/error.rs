@@ -0,0 +1,196 @@
+use std::{fmt, io, sync::PoisonError};
+
+use rocket::{Request, http::Status, response::{self, Responder, Response}, serde::json::Json};
+use serde_json::{Value, json};
+
+use crate::{pairing::PairError, versions::v1::{client_path::PathError, upload::conflict::ConflictResponse}};
+
+// everything a request can fail with, sent as {"code": "...", "error": "...", ...} with the variants details next to them
+// codes are for clients to match on and never change, the messages are for people
+#[derive(Debug)]
+pub enum ApiError {
+    // the body or a parameter doesn't make sense, e.g. an unknown container
+    BadRequest(String),
+    InvalidPath(String, PathError),
+    // pairs of paths that would be the same file or folder on the console
+    PathCollision(Vec<(String, String)>),
+
+    MalformedTicket(String),
+    UnknownTicket(String),
+    // e.g. a download ticket used to upload
+    WrongTicketType(String),
+    // a path that isn't in the manifest of the upload
+    NotRequested(String),
+    // a file whose size or hash isn't what the manifest declared
+    ManifestMismatch(String),
+    // a chunk whose size or hash isn't what the client declared
+    ChunkMismatch(String),
+    // chunks have to continue from the confirmed offset
+    WrongOffset(String, u64),
+    FileTooLarge(String),
+    // requested files that were never received, and received ones that don't match the manifest
+    Incomplete(Vec<String>, Vec<String>),
+    Conflict(Box<ConflictResponse>),
+    NotFound(String),
+
+    NotPairing,
+    WrongPin,
+    LockedOut,
+    NameTaken(String),
+
+    // rejected before reaching a handler, e.g. by a request guard or because no route matched
+    Rejected(Status),
+    // logged, clients only get a generic message
+    Internal(String)
+}
+
+impl ApiError {
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::BadRequest(_)       => Status::BadRequest,
+            ApiError::InvalidPath(..)     => Status::BadRequest,
+            ApiError::PathCollision(_)    => Status::BadRequest,
+            ApiError::MalformedTicket(_)  => Status::BadRequest,
+            ApiError::UnknownTicket(_)    => Status::NotFound,
+            ApiError::WrongTicketType(_)  => Status::BadRequest,
+            ApiError::NotRequested(_)     => Status::BadRequest,
+            ApiError::ManifestMismatch(_) => Status::UnprocessableEntity,
+            ApiError::ChunkMismatch(_)    => Status::BadRequest,
+            ApiError::WrongOffset(..)     => Status::Conflict,
+            ApiError::FileTooLarge(_)     => Status::PayloadTooLarge,
+            ApiError::Incomplete(..)      => Status::UnprocessableEntity,
+            ApiError::Conflict(_)         => Status::Conflict,
+            ApiError::NotFound(_)         => Status::NotFound,
+            ApiError::NotPairing          => Status::NotFound,
+            ApiError::WrongPin            => Status::Forbidden,
+            ApiError::LockedOut           => Status::TooManyRequests,
+            ApiError::NameTaken(_)        => Status::Conflict,
+            ApiError::Rejected(status)    => *status,
+            ApiError::Internal(_)         => Status::InternalServerError
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_)       => "BAD_REQUEST",
+            ApiError::InvalidPath(..)     => "INVALID_PATH",
+            ApiError::PathCollision(_)    => "PATH_COLLISION",
+            ApiError::MalformedTicket(_)  => "MALFORMED_TICKET",
+            ApiError::UnknownTicket(_)    => "UNKNOWN_TICKET",
+            ApiError::WrongTicketType(_)  => "WRONG_TICKET_TYPE",
+            ApiError::NotRequested(_)     => "NOT_REQUESTED",
+            ApiError::ManifestMismatch(_) => "MANIFEST_MISMATCH",
+            ApiError::ChunkMismatch(_)    => "CHUNK_MISMATCH",
+            ApiError::WrongOffset(..)     => "WRONG_OFFSET",
+            ApiError::FileTooLarge(_)     => "FILE_TOO_LARGE",
+            ApiError::Incomplete(..)      => "INCOMPLETE_UPLOAD",
+            ApiError::Conflict(_)         => "CONFLICT",
+            ApiError::NotFound(_)         => "NOT_FOUND",
+            ApiError::NotPairing          => "NOT_PAIRING",
+            ApiError::WrongPin            => "WRONG_PIN",
+            ApiError::LockedOut           => "LOCKED_OUT",
+            ApiError::NameTaken(_)        => "NAME_TAKEN",
+            ApiError::Rejected(status)    => match status.code {
+                400 => "BAD_REQUEST",
+                401 => "UNAUTHORIZED",
+                404 => "NOT_FOUND",
+                413 => "FILE_TOO_LARGE",
+                415 => "UNSUPPORTED_MEDIA_TYPE",
+                422 => "INVALID_BODY",
+                500 => "INTERNAL_ERROR",
+                _   => "REQUEST_FAILED"
+            },
+            ApiError::Internal(_)         => "INTERNAL_ERROR"
+        }
+    }
+
+    // sent next to the code and message
+    fn details(&self) -> Option<Value> {
+        match self {
+            ApiError::InvalidPath(path, _) | ApiError::NotRequested(path) | ApiError::ManifestMismatch(path) | ApiError::ChunkMismatch(path) | ApiError::FileTooLarge(path) => Some(json!({ "path": path })),
+            ApiError::WrongOffset(path, offset) => Some(json!({ "path": path, "offset": offset })),
+            ApiError::PathCollision(collisions) => Some(json!({ "collisions": collisions })),
+            ApiError::Incomplete(missing, mismatched) => Some(json!({ "missing": missing, "mismatched": mismatched })),
+            ApiError::Conflict(conflict) => serde_json::to_value(conflict).ok(),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(message)                => write!(f, "{message}"),
+            ApiError::InvalidPath(path, err)             => write!(f, "{path} isn't a valid path: {err}"),
+            ApiError::PathCollision(collisions)          => write!(f, "{} paths would be the same file on the console", collisions.len()),
+            ApiError::MalformedTicket(ticket)            => write!(f, "{ticket} isn't a ticket"),
+            ApiError::UnknownTicket(ticket)              => write!(f, "Ticket {ticket} doesn't exist or has expired"),
+            ApiError::WrongTicketType(ticket)            => write!(f, "Ticket {ticket} is for something else"),
+            ApiError::NotRequested(path)                 => write!(f, "{path} isn't part of the upload"),
+            ApiError::ManifestMismatch(path)             => write!(f, "{path} doesn't match the manifest"),
+            ApiError::ChunkMismatch(path)                => write!(f, "The chunk of {path} doesn't match its size or hash"),
+            ApiError::WrongOffset(path, offset)          => write!(f, "{path} continues at offset {offset}"),
+            ApiError::FileTooLarge(path)                 => write!(f, "{path} is over the file size limit"),
+            ApiError::Incomplete(missing, mismatched)    => write!(f, "{} requested files are missing and {} don't match the manifest", missing.len(), mismatched.len()),
+            ApiError::Conflict(conflict)                 => write!(f, "{}", conflict.message()),
+            ApiError::NotFound(message)                  => write!(f, "{message}"),
+            ApiError::NotPairing                         => write!(f, "The server isn't pairing"),
+            ApiError::WrongPin                           => write!(f, "Wrong pin"),
+            ApiError::LockedOut                          => write!(f, "Too many wrong pins, try again later"),
+            ApiError::NameTaken(name)                    => write!(f, "A device named \"{name}\" already exists"),
+            ApiError::Rejected(status)                   => write!(f, "{}", status.reason_lossy()),
+            ApiError::Internal(message)                  => write!(f, "{message}")
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let message = match &self {
+            ApiError::Internal(message) => {
+                println!("{message}");
+                "Internal server error".to_string()
+            },
+            _ => self.to_string()
+        };
+
+        let mut body = json!({ "code": self.code(), "error": message });
+        if let (Some(Value::Object(details)), Some(body)) = (self.details(), body.as_object_mut()) {
+            body.extend(details);
+        }
+
+        Response::build_from(Json(body).respond_to(request)?).status(self.status()).ok()
+    }
+}
+
+// invalid paths are the clients fault, anything else is ours
+impl From<io::Error> for ApiError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::InvalidInput => ApiError::BadRequest(err.to_string()),
+            _ => ApiError::Internal(err.to_string())
+        }
+    }
+}
+
+impl<T> From<PoisonError<T>> for ApiError {
+    fn from(_: PoisonError<T>) -> Self {
+        ApiError::Internal("A lock is poisoned".to_string())
+    }
+}
+
+impl From<PairError> for ApiError {
+    fn from(err: PairError) -> Self {
+        match err {
+            PairError::NotPairing => ApiError::NotPairing,
+            PairError::WrongPin => ApiError::WrongPin,
+            PairError::LockedOut => ApiError::LockedOut
+        }
+    }
+}
+
+// errors rocket produces itself get the same json body as the ones from handlers
+#[catch(default)]
+pub fn default_catcher(status: Status, _request: &Request) -> ApiError {
+    ApiError::Rejected(status)
+}
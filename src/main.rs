@@ -1,11 +1,15 @@
-use std::{collections::HashMap, sync::{Arc, Mutex}};
+use std::sync::{Arc, Mutex};
 
 use rocket::{data::{Limits, ToByteUnit}, tokio};
-use crate::{config::Config, versions::v1};
+use crate::{config::Config, devices::{DeviceStore, Devices}, pairing::Pairing, versions::v1};
 
 #[macro_use] extern crate rocket;
 
 pub mod config;
+pub mod devices;
+pub mod error;
+pub mod pairing;
+pub mod tls;
 pub mod versions;
 
 #[cfg(feature = "tray")]
@@ -13,37 +17,96 @@ pub mod tray_app;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // cleanup previous if exists
-    v1::ticket::clear_tickets_path().expect("Failed to clear old tickets path");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if devices::run_command(&args) {
+        return Ok(())
+    }
 
-    let tickets: v1::ticket::Tickets = Arc::new(Mutex::new(HashMap::new()));
     let config = Config::load();
+    let tickets: v1::ticket::Tickets = Arc::new(Mutex::new(v1::ticket::load_tickets(&config)));
+    let devices: Devices = Arc::new(Mutex::new(DeviceStore::load()));
+    if devices.lock().map(|mut store| store.devices().is_empty()).unwrap_or(true) {
+        println!("No devices are registered, pair one from the client or add one with: SaveSyncd device add <name>");
+    }
+
+    let file_index = v1::hash_index::FileIndex::load(&config);
+    if let Err(err) = v1::history::migrate_revisions(&config, &file_index) {
+        println!("Failed to move revisions into the blob store: {err}");
+    }
+
+    // interrupted commits have pending revisions whose blobs aren't referenced yet, so they're recovered first
+    let index: v1::hash_index::HashIndex = Arc::new(file_index);
+    let fs_storage = v1::storage::filesystem::FsStorage::new(config.clone(), index.clone());
+    match fs_storage.recover() {
+        Err(err) => println!("Failed to recover interrupted commits, unreferenced blobs are kept: {err}"),
+        Ok(()) => match v1::blobs::collect_garbage(&config) {
+            Ok(0) => {},
+            Ok(removed) => println!("Removed {removed} unreferenced blobs"),
+            Err(err) => println!("Failed to collect unreferenced blobs: {err}")
+        }
+    }
+
+    let storage: v1::storage::Store = Arc::new(fs_storage);
+    let pairing: Pairing = Arc::new(Mutex::new(Default::default()));
         
-    let figment = rocket::Config::figment()
+    let mut figment = rocket::Config::figment()
         .merge(("address", "0.0.0.0"))
         .merge(("port", config.port()))
         .merge(("limits", Limits::new()
-            .limit("bytes", 100.mebibytes())
-            .limit("json", 8.mebibytes())
+            .limit("json", config.json_limit().bytes())
         ));
 
+    // serving plain HTTP when TLS was asked for would send tokens in the clear
+    let certificate = match (config.tls_cert(), config.tls_key()) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (Some(_), None) => return Err("tls_cert is set without tls_key, set both or neither".into()),
+        (None, Some(_)) => return Err("tls_key is set without tls_cert, set both or neither".into()),
+        (None, None) if config.tls_self_signed() => Some(tls::self_signed_certificate()?),
+        (None, None) => None
+    };
+
+    if let Some((cert, key)) = certificate {
+        match tls::fingerprint(&cert) {
+            Ok(fingerprint) => println!("TLS certificate fingerprint (SHA-256): {fingerprint}"),
+            Err(err) => println!("Failed to get fingerprint of {}: {err}", cert.display())
+        }
+
+        figment = figment.merge(("tls", rocket::config::TlsConfig::from_paths(cert, key)));
+    }
+
     let rocket = rocket::custom(figment)
-        .manage(tickets)
-        .manage(config)
+        .manage(tickets.clone())
+        .manage(config.clone())
+        .manage(devices)
+        .manage(index)
+        .manage(v1::locks::TitleLocks::default())
+        .manage(storage.clone())
+        .manage(pairing.clone())
         .mount("/", routes![
             v1::status_get,
             v1::status_head,
+            v1::pair::pair_begin,
+            v1::pair::pair,
             v1::titles::titles,
 
             v1::upload::begin::upload_begin,
             v1::upload::file::upload_file,
+            v1::upload::chunk::upload_chunk,
             v1::upload::end::upload_end,
             v1::upload::cancel::upload_cancel,
+            v1::upload::status::upload_status,
 
             v1::download::begin::download_begin,
             v1::download::file::download_file,
-            v1::download::end::download_end
-        ]).ignite().await?;
+            v1::download::end::download_end,
+
+            v1::revisions::list::revisions_list,
+            v1::revisions::restore::revisions_restore
+        ])
+        .register("/", catchers![error::default_catcher])
+        .ignite().await?;
+
+    tokio::spawn(v1::ticket::reap_tickets_task(tickets, config, storage));
 
     let _shutdown = rocket.shutdown();
     let rocket_handle = tokio::spawn(async move {
@@ -53,7 +116,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     #[cfg(feature = "tray")]
     {
         use crate::tray_app::Application;
-        let app = Application::new();
+        let app = Application::new(pairing);
 
         app.run();
         _shutdown.notify();
@@ -65,7 +128,5 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let _ = tokio::join!(rocket_handle);
     }
 
-    v1::ticket::clear_tickets_path().expect("Failed to cleanup tickets path");
-
     Ok(())
 }
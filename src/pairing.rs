@@ -0,0 +1,223 @@
+use std::{collections::HashMap, net::IpAddr, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use rocket::tokio;
+use uuid::Uuid;
+
+pub const PIN_LIFETIME: Duration = Duration::from_secs(120);
+// wrong guesses are counted within this window, across pairing sessions
+const GUESS_WINDOW: Duration = Duration::from_secs(60 * 60);
+// a client that guesses wrong this many times can't pair or start pairing for LOCKOUT
+const MAX_ATTEMPTS: usize = 3;
+const LOCKOUT: Duration = Duration::from_secs(300);
+// the pin is burned after this many wrong guesses from all clients together
+const MAX_SESSION_ATTEMPTS: u32 = 10;
+// pairing is paused for every client after this many wrong guesses from all clients within GUESS_WINDOW
+// so many addresses can't brute force the pin between them by starting new sessions
+const MAX_WINDOW_ATTEMPTS: usize = 30;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PairError {
+    NotPairing,
+    WrongPin,
+    LockedOut
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    pin: String,
+    expires: Instant,
+    attempts: u32
+}
+
+#[derive(Debug, Clone, Default)]
+struct Client {
+    wrong_guesses: Vec<Instant>,
+    locked_until: Option<Instant>
+}
+
+// lockouts are per client address, so one host guessing wrong can't keep every console from pairing
+// only many wrong guesses from all clients together pause pairing for everyone
+#[derive(Debug, Default)]
+pub struct PairingState {
+    session: Option<Session>,
+    clients: HashMap<IpAddr, Client>,
+    wrong_guesses: Vec<Instant>
+}
+
+fn recent(guesses: &mut Vec<Instant>, now: Instant) {
+    guesses.retain(|at| now.duration_since(*at) < GUESS_WINDOW);
+}
+
+pub type Pairing = Arc<Mutex<PairingState>>;
+
+impl PairingState {
+    pub fn is_paused(&self) -> bool {
+        self.wrong_guesses.iter().filter(|at| at.elapsed() < GUESS_WINDOW).count() >= MAX_WINDOW_ATTEMPTS
+    }
+
+    pub fn is_locked(&self, client: IpAddr) -> bool {
+        self.is_paused() || self.clients.get(&client).and_then(|client| client.locked_until).is_some_and(|until| Instant::now() < until)
+    }
+
+    fn active_pin(&self) -> Option<&str> {
+        self.session.as_ref().filter(|session| Instant::now() < session.expires).map(|session| session.pin.as_str())
+    }
+
+    // a correct pin ends the session, so it can only pair a single device
+    pub fn verify(&mut self, client: IpAddr, pin: &str) -> Result<(), PairError> {
+        if self.is_locked(client) {
+            return Err(PairError::LockedOut)
+        }
+
+        // clients whose lockout ended and that have no wrong guesses left to count
+        let now = Instant::now();
+        recent(&mut self.wrong_guesses, now);
+        self.clients.retain(|_, client| {
+            recent(&mut client.wrong_guesses, now);
+            !client.wrong_guesses.is_empty() || client.locked_until.is_some_and(|until| now < until)
+        });
+
+        let Some(expected) = self.active_pin() else { return Err(PairError::NotPairing) };
+        if expected == pin.trim() {
+            self.session = None;
+            self.clients.remove(&client);
+            announce(None);
+
+            return Ok(())
+        }
+
+        let Some(session) = self.session.as_mut() else { return Err(PairError::NotPairing) };
+        session.attempts += 1;
+        let burned = session.attempts >= MAX_SESSION_ATTEMPTS;
+
+        self.wrong_guesses.push(now);
+        if self.is_paused() {
+            self.session = None;
+            println!("Too many wrong pairing PINs from all clients, pairing is paused for up to {} minutes. Devices can still be added with: SaveSyncd device add <name>", GUESS_WINDOW.as_secs() / 60);
+            announce(None);
+        } else if burned {
+            self.session = None;
+            println!("Too many wrong pairing PINs, the PIN is no longer valid");
+            announce(None);
+        }
+
+        let entry = self.clients.entry(client).or_default();
+        entry.wrong_guesses.push(now);
+        println!("Wrong pairing PIN entered by {client} ({}/{MAX_ATTEMPTS})", entry.wrong_guesses.len());
+
+        if entry.wrong_guesses.len() >= MAX_ATTEMPTS {
+            *entry = Client { wrong_guesses: Vec::new(), locked_until: Some(now + LOCKOUT) };
+            println!("Too many wrong pairing PINs, {client} is locked out of pairing for {} seconds", LOCKOUT.as_secs());
+
+            return Err(PairError::LockedOut)
+        }
+
+        Err(PairError::WrongPin)
+    }
+}
+
+fn announce(pin: Option<&str>) {
+    match pin {
+        Some(pin) => println!("Pairing PIN: {pin} (valid for {} seconds)", PIN_LIFETIME.as_secs()),
+        None => println!("Pairing ended")
+    }
+
+    #[cfg(feature = "tray")]
+    crate::tray_app::PairingEvent::send(crate::tray_app::PairingEvent { pin: pin.map(str::to_string) });
+}
+
+// starts pairing mode, or returns the pin of the already running session
+pub fn start_pairing(pairing: &Pairing) -> Option<String> {
+    let mut state = pairing.lock().ok()?;
+    if let Some(pin) = state.active_pin() {
+        return Some(pin.to_string())
+    }
+
+    // wrong guesses and lockouts carry over, so starting a new session doesn't give anyone more guesses
+    let pin = format!("{:06}", Uuid::new_v4().as_u128() % 1_000_000);
+    state.session = Some(Session { pin: pin.clone(), expires: Instant::now() + PIN_LIFETIME, attempts: 0 });
+    announce(Some(&pin));
+
+    let pairing = pairing.clone();
+    let expired_pin = pin.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(PIN_LIFETIME).await;
+
+        let Ok(mut state) = pairing.lock() else { return };
+        if state.session.as_ref().is_some_and(|session| session.pin == expired_pin) {
+            state.session = None;
+            announce(None);
+        }
+    });
+
+    Some(pin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairing(pin: &str) -> PairingState {
+        PairingState { session: Some(session(pin)), ..Default::default() }
+    }
+
+    fn session(pin: &str) -> Session {
+        Session { pin: pin.to_string(), expires: Instant::now() + PIN_LIFETIME, attempts: 0 }
+    }
+
+    #[test]
+    fn lockouts_are_per_client() {
+        let mut state = pairing("123456");
+        let attacker: IpAddr = "192.168.1.66".parse().unwrap();
+        let console: IpAddr = "192.168.1.3".parse().unwrap();
+
+        assert_eq!(state.verify(attacker, "000000"), Err(PairError::WrongPin));
+        assert_eq!(state.verify(attacker, "000001"), Err(PairError::WrongPin));
+        assert_eq!(state.verify(attacker, "000002"), Err(PairError::LockedOut));
+        assert_eq!(state.verify(attacker, "123456"), Err(PairError::LockedOut));
+        assert!(state.is_locked(attacker) && !state.is_locked(console));
+
+        assert_eq!(state.verify(console, "123456"), Ok(()));
+    }
+
+    #[test]
+    fn pin_is_burned_after_too_many_wrong_guesses() {
+        let mut state = pairing("123456");
+        for attempt in 0..MAX_SESSION_ATTEMPTS {
+            let client = IpAddr::from([10, 0, 0, attempt as u8]);
+            assert_ne!(state.verify(client, "000000"), Ok(()));
+        }
+
+        assert_eq!(state.verify(IpAddr::from([10, 0, 1, 1]), "123456"), Err(PairError::NotPairing));
+    }
+
+    #[test]
+    fn wrong_guesses_carry_over_sessions() {
+        let mut state = pairing("123456");
+        let attacker: IpAddr = "192.168.1.66".parse().unwrap();
+
+        assert_eq!(state.verify(attacker, "000000"), Err(PairError::WrongPin));
+        assert_eq!(state.verify(attacker, "000001"), Err(PairError::WrongPin));
+
+        state.session = Some(session("654321"));
+        assert_eq!(state.verify(attacker, "000002"), Err(PairError::LockedOut));
+    }
+
+    #[test]
+    fn pairing_is_paused_after_too_many_wrong_guesses_from_all_clients() {
+        let mut state = pairing("123456");
+        for attempt in 0..MAX_WINDOW_ATTEMPTS {
+            // every address guesses once, and pairing is started again whenever the pin is burned
+            if state.session.is_none() {
+                state.session = Some(session("123456"));
+            }
+
+            assert_eq!(state.verify(IpAddr::from([10, 0, 0, attempt as u8]), "000000"), Err(PairError::WrongPin));
+        }
+
+        let console: IpAddr = "192.168.1.3".parse().unwrap();
+        state.session = Some(session("123456"));
+        assert!(state.is_locked(console));
+        assert_eq!(state.verify(console, "123456"), Err(PairError::LockedOut));
+    }
+}
@@ -0,0 +1,41 @@
+use std::{error::Error, fs, path::{Path, PathBuf}};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+
+fn self_signed_path() -> PathBuf {
+    Config::config_path().join("tls")
+}
+
+// generates the certificate on first use, and keeps reusing it so pinned fingerprints stay valid
+pub fn self_signed_certificate() -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+    let cert_path = self_signed_path().join("cert.pem");
+    let key_path = self_signed_path().join("key.pem");
+    if cert_path.exists() && key_path.exists() {
+        return Ok((cert_path, key_path))
+    }
+
+    let certified = rcgen::generate_simple_self_signed(vec!["SaveSyncd".to_string(), "localhost".to_string()])?;
+    fs::create_dir_all(self_signed_path())?;
+    fs::write(&key_path, certified.key_pair.serialize_pem())?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&key_path, fs::Permissions::from_mode(0o600))?;
+    }
+
+    fs::write(&cert_path, certified.cert.pem())?;
+    println!("Generated self-signed certificate at {}", cert_path.display());
+
+    Ok((cert_path, key_path))
+}
+
+// SHA-256 of the first certificate in a PEM file, formatted as colon separated hex
+pub fn fingerprint(cert: &Path) -> Result<String, Box<dyn Error>> {
+    let pems = pem::parse_many(fs::read(cert)?)?;
+    let Some(pem) = pems.iter().find(|pem| pem.tag() == "CERTIFICATE") else { return Err("No certificate found".into()) };
+
+    Ok(Sha256::digest(pem.contents()).iter().map(|byte| format!("{byte:02X}")).collect::<Vec<String>>().join(":"))
+}
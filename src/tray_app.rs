@@ -5,6 +5,8 @@ use tray_icon::{TrayIcon, TrayIconBuilder, TrayIconEvent, menu::{Menu, MenuEvent
 use winit::{application::ApplicationHandler, event_loop::EventLoop};
 use once_cell::sync::{OnceCell, Lazy};
 
+use crate::pairing::{Pairing, start_pairing};
+
 #[derive(Debug, Clone)]
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct QuitEvent {}
@@ -37,25 +39,83 @@ impl QuitEvent {
     }
 }
 
+// sent whenever pairing mode starts (with the pin to show) or ends
+#[derive(Debug, Clone)]
+pub struct PairingEvent {
+    pub pin: Option<String>
+}
+
+pub type PairingEventReceiver = Receiver<PairingEvent>;
+type PairingEventHandler = Box<dyn Fn(PairingEvent) + Send + Sync + 'static>;
+
+static PAIRING_CHANNEL: Lazy<(Sender<PairingEvent>, PairingEventReceiver)> = Lazy::new(unbounded);
+static PAIRING_EVENT_HANDLER: OnceCell<Option<PairingEventHandler>> = OnceCell::new();
+
+impl PairingEvent {
+    pub fn receiver<'a>() -> &'a PairingEventReceiver {
+        &PAIRING_CHANNEL.1
+    }
+
+    pub fn set_event_handler<F: Fn(PairingEvent) + Send + Sync + 'static>(f: Option<F>) {
+        if let Some(f) = f {
+            let _ = PAIRING_EVENT_HANDLER.set(Some(Box::new(f)));
+        } else {
+            let _ = PAIRING_EVENT_HANDLER.set(None);
+        }
+    }
+
+    pub fn send(event: PairingEvent) {
+        if let Some(handler) = PAIRING_EVENT_HANDLER.get_or_init(|| None) {
+            handler(event);
+        } else {
+            let _ = PAIRING_CHANNEL.0.send(event);
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum UserEvent {
     TrayIconEvent(tray_icon::TrayIconEvent),
     MenuEvent(tray_icon::menu::MenuEvent),
-    QuitEvent(QuitEvent)
+    QuitEvent(QuitEvent),
+    #[cfg_attr(target_os = "linux", allow(dead_code))]
+    PairingEvent(PairingEvent)
 }
 
 pub struct Application {
     pub tray_icon: Option<TrayIcon>,
+    pub pair_button: Option<MenuItem>,
+    pairing: Pairing
 }
 
 impl Application {
-    pub fn new() -> Application {
+    pub fn new(pairing: Pairing) -> Application {
         Application {
-            tray_icon: None
+            tray_icon: None,
+            pair_button: None,
+            pairing
         }
     }
 
-    pub fn new_tray_icon() -> TrayIcon {
+    pub fn new_pair_button() -> MenuItem {
+        MenuItem::with_id("pair", "Pair Device", true, None)
+    }
+
+    // the pair button doubles as the pin display while pairing
+    pub fn update_pair_button(button: &MenuItem, event: &PairingEvent) {
+        match &event.pin {
+            Some(pin) => {
+                button.set_text(format!("Pairing PIN: {pin}"));
+                button.set_enabled(false);
+            },
+            None => {
+                button.set_text("Pair Device");
+                button.set_enabled(true);
+            }
+        }
+    }
+
+    pub fn new_tray_icon(pair_button: &MenuItem) -> TrayIcon {
         let bytes = include_bytes!("../assets/icon.svg");
         let tree = resvg::usvg::Tree::from_data(bytes, &usvg::Options::default()).expect("Failed to load SVG");
 
@@ -64,7 +124,7 @@ impl Application {
 
         let icon = tray_icon::Icon::from_rgba(pixmap.data().to_vec(), pixmap.width(), pixmap.height()).expect("Failed to read icon");
         TrayIconBuilder::new()
-            .with_menu(Box::new(Self::new_tray_menu()))
+            .with_menu(Box::new(Self::new_tray_menu(pair_button)))
             .with_tooltip("winit - awesome windowing lib")
             .with_icon(icon)
             .with_title("x")
@@ -72,10 +132,10 @@ impl Application {
             .unwrap()
     }
 
-    pub fn new_tray_menu() -> Menu {
+    pub fn new_tray_menu(pair_button: &MenuItem) -> Menu {
         let menu = Menu::new();
         let close_button = MenuItem::with_id("close", "Close", true, None);
-        if let Err(err) = menu.append(&close_button) {
+        if let Err(err) = menu.append_items(&[pair_button, &close_button]) {
             println!("{err:?}");
         }
 
@@ -90,6 +150,13 @@ impl Application {
 
         let quit_proxy = event_loop.create_proxy();
         QuitEvent::set_event_handler(Some(move |event| { let _ = quit_proxy.send_event(UserEvent::QuitEvent(event)); }));
+
+        // on linux the menu lives on the gtk thread, which reads the pairing channel itself
+        #[cfg(not(target_os = "linux"))]
+        {
+            let pairing_proxy = event_loop.create_proxy();
+            PairingEvent::set_event_handler(Some(move |event| { let _ = pairing_proxy.send_event(UserEvent::PairingEvent(event)); }));
+        }
         
         let _menu_channel = MenuEvent::receiver();
         let _tray_channel = TrayIconEvent::receiver();
@@ -97,7 +164,17 @@ impl Application {
         #[cfg(target_os = "linux")]
         std::thread::spawn(|| {
             gtk::init().unwrap();
-            let _tray_icon = Application::new_tray_icon();
+            let pair_button = Application::new_pair_button();
+            let _tray_icon = Application::new_tray_icon(&pair_button);
+
+            gtk::glib::timeout_add_local(std::time::Duration::from_millis(250), move || {
+                while let Ok(event) = PairingEvent::receiver().try_recv() {
+                    Application::update_pair_button(&pair_button, &event);
+                }
+
+                gtk::glib::ControlFlow::Continue
+            });
+
             gtk::main();
         });
 
@@ -133,7 +210,9 @@ impl ApplicationHandler<UserEvent> for Application {
         if winit::event::StartCause::Init == cause {
             #[cfg(not(target_os = "linux"))]
             {
-                self.tray_icon = Some(Self::new_tray_icon());
+                let pair_button = Self::new_pair_button();
+                self.tray_icon = Some(Self::new_tray_icon(&pair_button));
+                self.pair_button = Some(pair_button);
             }
 
             // We have to request a redraw here to have the icon actually show up.
@@ -156,10 +235,20 @@ impl ApplicationHandler<UserEvent> for Application {
                 return
             }
 
+            if event.id == "pair" {
+                start_pairing(&self.pairing);
+                return
+            }
+
             println!("unhandled menu: {event:?}");
         },
         UserEvent::QuitEvent(_) => {
             event_loop.exit();
+        },
+        UserEvent::PairingEvent(event) => {
+            if let Some(button) = &self.pair_button {
+                Self::update_pair_button(button, &event);
+            }
         }
         _ => {
             println!("unhandled event: {event:?}");
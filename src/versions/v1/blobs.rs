@@ -0,0 +1,149 @@
+use std::{collections::HashMap, fs, io, path::{Path, PathBuf}};
+
+use uuid::Uuid;
+
+use crate::{config::Config, versions::v1::{file_info::HashAlgorithm, hash_index::FileIndex, history::{list_revisions_strict, title_ids}, storage::filesystem::{sync_dir, sync_file}, ticket::Container}};
+
+// file contents are stored once in <data_directory>/.blobs/<first 2 hex digits>/<sha256>,
+// revision manifests refer to them by hash. blobs are never written to after they're stored
+
+pub fn blobs_path(config: &Config) -> PathBuf {
+    config.data_directory().join(".blobs")
+}
+
+pub fn blob_path(config: &Config, hash: &str) -> PathBuf {
+    blobs_path(config).join(hash.get(..2).unwrap_or("00")).join(hash)
+}
+
+// returns the hash the file is stored under, files that are already stored aren't copied again
+pub fn store_blob(config: &Config, index: &FileIndex, path: &Path) -> io::Result<String> {
+    let hash = index.hash(path, HashAlgorithm::SHA256)?;
+    let blob = blob_path(config, &hash);
+
+    // blobs are synced before they're renamed into place, but ones cut short by older versions are stored again
+    let size = fs::metadata(path)?.len();
+    if fs::metadata(&blob).is_ok_and(|metadata| metadata.len() == size) {
+        return Ok(hash)
+    }
+
+    let Some(parent) = blob.parent() else { return Err(io::Error::other("Blob path has no parent")) };
+    fs::create_dir_all(parent)?;
+
+    // commits of different titles may store the same blob at once, each copy gets its own name until it's renamed
+    let temp_path = blob.with_extension(format!("{}.tmp", Uuid::new_v4().simple()));
+    fs::copy(path, &temp_path)?;
+    sync_file(&temp_path)?;
+    fs::rename(temp_path, &blob)?;
+    sync_dir(parent)?;
+
+    Ok(hash)
+}
+
+fn replace_file(path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(())
+    }
+}
+
+// for read only copies like download staging, the file shares the blobs bytes when the filesystem allows it
+pub fn link_blob(config: &Config, hash: &str, path: &Path) -> io::Result<()> {
+    replace_file(path)?;
+    if fs::hard_link(blob_path(config, hash), path).is_err() {
+        fs::copy(blob_path(config, hash), path)?;
+    }
+
+    Ok(())
+}
+
+// for files that may be changed later, like the live container
+pub fn copy_blob(config: &Config, hash: &str, path: &Path) -> io::Result<()> {
+    replace_file(path)?;
+    fs::copy(blob_path(config, hash), path)?;
+
+    Ok(())
+}
+
+// counts how many revisions refer to every blob, and deletes the blobs nothing refers to
+// returns the number of deleted blobs. nothing is deleted if any revision can't be read, its blobs could still be needed
+pub fn collect_garbage(config: &Config) -> io::Result<usize> {
+    if !blobs_path(config).exists() {
+        return Ok(0)
+    }
+
+    let mut references: HashMap<String, usize> = HashMap::new();
+    for title_id in title_ids(config) {
+        for container in [Container::SAVE, Container::EXTDATA] {
+            for revision in list_revisions_strict(config, title_id, container)? {
+                for hash in revision.blobs.values() {
+                    *references.entry(hash.clone()).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    let mut removed = 0;
+    for prefix in fs::read_dir(blobs_path(config))? {
+        let prefix = prefix?;
+        if !prefix.file_type()?.is_dir() {
+            continue;
+        }
+
+        for blob in fs::read_dir(prefix.path())? {
+            let blob = blob?;
+            let Some(name) = blob.file_name().to_str().map(str::to_string) else { continue; };
+            if references.get(&name).is_some_and(|count| *count > 0) {
+                continue;
+            }
+
+            fs::remove_file(blob.path())?;
+            removed += 1;
+        }
+
+        // only succeeds once the directory is empty
+        let _ = fs::remove_dir(prefix.path());
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::versions::v1::history::{history_path, prepare_revision, publish_revision};
+
+    const TITLE: u64 = 0x0004000000055D00;
+
+    #[test]
+    fn unreadable_revisions_keep_every_blob() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::new(dir.path().to_path_buf());
+        let index = FileIndex::load(&config);
+
+        let container_path = config.data_directory().join(format!("{:X}", TITLE)).join("save");
+        fs::create_dir_all(&container_path).unwrap();
+        fs::write(container_path.join("GameData.bin"), b"kept").unwrap();
+        let revision = prepare_revision(&config, &index, TITLE, Container::SAVE, &container_path, "test", None, &HashMap::new()).unwrap();
+        publish_revision(&config, TITLE, Container::SAVE, revision.id).unwrap();
+        let kept = blob_path(&config, &revision.blobs["GameData.bin"]);
+
+        fs::write(dir.path().join("unreferenced"), b"unreferenced").unwrap();
+        let unreferenced = blob_path(&config, &store_blob(&config, &index, &dir.path().join("unreferenced")).unwrap());
+
+        // a revision that can't be read may refer to any blob
+        let corrupt = history_path(&config, TITLE, Container::SAVE).join("1");
+        fs::create_dir_all(&corrupt).unwrap();
+        fs::write(corrupt.join("revision.json"), b"{").unwrap();
+        assert!(collect_garbage(&config).is_err());
+        assert!(kept.exists() && unreferenced.exists());
+
+        // one that was never finished has no revision.json, and refers to nothing
+        fs::remove_file(corrupt.join("revision.json")).unwrap();
+        assert_eq!(collect_garbage(&config).unwrap(), 1);
+        assert!(kept.exists() && !unreferenced.exists());
+    }
+}
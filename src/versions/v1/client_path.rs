@@ -0,0 +1,277 @@
+use std::{collections::HashMap, fmt, path::{Path, PathBuf}};
+
+use crate::versions::v1::file_info::EntryKind;
+
+// every path a client sends is parsed into a ClientPath before it's used, so nothing outside a container can be named
+// clients send paths like /Folder/GameData.bin, the leading slash is optional and . components are dropped.
+// the canonical form (see Display) always has the leading slash, that's what manifests and tickets compare against
+//
+// the console's filesystems are case insensitive, so two paths are the same file when their keys are equal.
+// the spelling is kept as the client sent it, and the container uses the spelling of the newest manifest
+
+pub const MAX_COMPONENT_LENGTH: usize = 255;
+pub const MAX_PATH_LENGTH: usize = 1024;
+
+// names Windows won't create files with, with or without an extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9"
+];
+
+// invalid on FAT and Windows, and \ and : can also start a drive or a share when joined on Windows
+const INVALID_CHARACTERS: [char; 8] = ['<', '>', ':', '"', '\\', '|', '?', '*'];
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PathError {
+    Empty,
+    TooLong,
+    ParentDirectory,
+    EmptyComponent,
+    InvalidCharacter(char),
+    ReservedName(String),
+    TrailingDotOrSpace(String)
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Empty                       => write!(f, "The path is empty"),
+            PathError::TooLong                     => write!(f, "The path or one of its names is too long"),
+            PathError::ParentDirectory             => write!(f, "The path contains .."),
+            PathError::EmptyComponent              => write!(f, "The path contains an empty name"),
+            PathError::InvalidCharacter(character) => write!(f, "The path contains the invalid character {character:?}"),
+            PathError::ReservedName(name)          => write!(f, "{name} is a reserved name"),
+            PathError::TrailingDotOrSpace(name)    => write!(f, "{name} ends with a dot or a space")
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash, PartialOrd, Ord)]
+pub struct ClientPath {
+    // the names of the path, none of them empty, . or ..
+    components: Vec<String>
+}
+
+impl ClientPath {
+    pub fn parse(path: &str) -> Result<Self, PathError> {
+        if path.len() > MAX_PATH_LENGTH {
+            return Err(PathError::TooLong)
+        }
+
+        let relative = path.strip_prefix("/").unwrap_or(path);
+        let mut components = Vec::new();
+        for component in relative.split('/') {
+            match component {
+                "." => continue,
+                ".." => return Err(PathError::ParentDirectory),
+                "" => return Err(PathError::EmptyComponent),
+                _ => {}
+            }
+
+            if component.len() > MAX_COMPONENT_LENGTH {
+                return Err(PathError::TooLong)
+            }
+
+            if let Some(character) = component.chars().find(|character| character.is_control() || INVALID_CHARACTERS.contains(character)) {
+                return Err(PathError::InvalidCharacter(character))
+            }
+
+            if component.ends_with('.') || component.ends_with(' ') {
+                return Err(PathError::TrailingDotOrSpace(component.to_string()))
+            }
+
+            let stem = component.split('.').next().unwrap_or(component).trim_end();
+            if RESERVED_NAMES.iter().any(|name| name.eq_ignore_ascii_case(stem)) {
+                return Err(PathError::ReservedName(component.to_string()))
+            }
+
+            components.push(component.to_string());
+        }
+
+        if components.is_empty() {
+            return Err(PathError::Empty)
+        }
+
+        Ok(ClientPath { components })
+    }
+
+    // the path relative to its container, without the leading slash
+    pub fn relative(&self) -> String {
+        self.components.join("/")
+    }
+
+    pub fn key(&self) -> String {
+        path_key(&self.relative())
+    }
+
+    pub fn join_to(&self, base: &Path) -> PathBuf {
+        let mut path = base.to_path_buf();
+        path.extend(&self.components);
+
+        path
+    }
+}
+
+impl fmt::Display for ClientPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/{}", self.relative())
+    }
+}
+
+// compares equal for paths that are the same file on the console, works for server paths without the leading slash too
+pub fn path_key(path: &str) -> String {
+    path.strip_prefix("/").unwrap_or(path).to_lowercase()
+}
+
+// the keys of the folders path is in, they exist even when they aren't listed
+pub fn folder_keys(path: &str) -> Vec<String> {
+    let key = path_key(path);
+    key.match_indices('/').map(|(index, _)| key[..index].to_string()).collect()
+}
+
+// every entry of a listing by its key, with the folders that are only implied by their contents
+// two listings with the same entries describe the same container, however their names are spelled
+pub fn entry_keys<'a>(entries: impl IntoIterator<Item = (&'a str, EntryKind)>) -> HashMap<String, EntryKind> {
+    let mut keys = HashMap::new();
+    for (path, kind) in entries {
+        for folder in folder_keys(path) {
+            keys.insert(folder, EntryKind::DIRECTORY);
+        }
+
+        keys.insert(path_key(path), kind);
+    }
+
+    keys
+}
+
+// pairs of paths that would end up as the same entry on the console: names that only differ in case,
+// the same path as a file and a directory, or a file with the name of a folder another entry is in.
+// the same entry listed twice isn't a collision
+pub fn find_collisions(entries: &[(ClientPath, EntryKind)]) -> Vec<(String, String)> {
+    let mut collisions = Vec::new();
+
+    let mut seen: HashMap<String, &(ClientPath, EntryKind)> = HashMap::new();
+    for entry in entries {
+        match seen.get(&entry.0.key()) {
+            Some(other) if *other != entry => collisions.push((other.0.to_string(), entry.0.to_string())),
+            Some(_) => {},
+            None => { seen.insert(entry.0.key(), entry); }
+        }
+    }
+
+    for (path, _) in entries {
+        for folder in folder_keys(&path.relative()) {
+            let Some((file, EntryKind::FILE)) = seen.get(&folder) else { continue; };
+            let collision = (file.to_string(), path.to_string());
+            if !collisions.contains(&collision) {
+                collisions.push(collision);
+            }
+        }
+    }
+
+    collisions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(path: &str) -> Result<String, PathError> {
+        ClientPath::parse(path).map(|path| path.to_string())
+    }
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(parse("/GameData.bin"), Ok("/GameData.bin".to_string()));
+        assert_eq!(parse("GameData.bin"), Ok("/GameData.bin".to_string()));
+        assert_eq!(parse("/./Folder/./GameData.bin"), Ok("/Folder/GameData.bin".to_string()));
+        assert_eq!(parse("/.hidden/..dots"), Ok("/.hidden/..dots".to_string()));
+        assert_eq!(ClientPath::parse("/Folder/GameData.bin").unwrap().relative(), "Folder/GameData.bin");
+    }
+
+    #[test]
+    fn traversal_is_rejected() {
+        assert_eq!(parse("/../../etc/passwd"), Err(PathError::ParentDirectory));
+        assert_eq!(parse("/Folder/../../x"), Err(PathError::ParentDirectory));
+        assert_eq!(parse(".."), Err(PathError::ParentDirectory));
+        assert_eq!(parse("//etc/passwd"), Err(PathError::EmptyComponent));
+        assert_eq!(parse("/Folder//x"), Err(PathError::EmptyComponent));
+        assert_eq!(parse("/Folder/"), Err(PathError::EmptyComponent));
+    }
+
+    #[test]
+    fn windows_escapes_are_rejected() {
+        assert_eq!(parse("..\\..\\x"), Err(PathError::InvalidCharacter('\\')));
+        assert_eq!(parse("C:\\Windows"), Err(PathError::InvalidCharacter(':')));
+        assert_eq!(parse("/C:x"), Err(PathError::InvalidCharacter(':')));
+        assert_eq!(parse("\\\\server\\share"), Err(PathError::InvalidCharacter('\\')));
+    }
+
+    #[test]
+    fn empty_paths_are_rejected() {
+        assert_eq!(parse(""), Err(PathError::EmptyComponent));
+        assert_eq!(parse("/"), Err(PathError::EmptyComponent));
+        assert_eq!(parse("/."), Err(PathError::Empty));
+    }
+
+    #[test]
+    fn hostile_names_are_rejected() {
+        assert_eq!(parse("/Game\0Data.bin"), Err(PathError::InvalidCharacter('\0')));
+        assert_eq!(parse("/Game\nData.bin"), Err(PathError::InvalidCharacter('\n')));
+        assert_eq!(parse("/what?.bin"), Err(PathError::InvalidCharacter('?')));
+        assert_eq!(parse("/con"), Err(PathError::ReservedName("con".to_string())));
+        assert_eq!(parse("/Folder/NUL.txt"), Err(PathError::ReservedName("NUL.txt".to_string())));
+        assert_eq!(parse("/LPT1 .bin"), Err(PathError::ReservedName("LPT1 .bin".to_string())));
+        assert_eq!(parse("/GameData.bin."), Err(PathError::TrailingDotOrSpace("GameData.bin.".to_string())));
+        assert_eq!(parse("/GameData "), Err(PathError::TrailingDotOrSpace("GameData ".to_string())));
+        assert!(parse("/CONFIG.bin").is_ok());
+    }
+
+    #[test]
+    fn long_paths_are_rejected() {
+        assert!(parse(&"a".repeat(MAX_COMPONENT_LENGTH)).is_ok());
+        assert_eq!(parse(&"a".repeat(MAX_COMPONENT_LENGTH + 1)), Err(PathError::TooLong));
+        assert_eq!(parse(&"/a".repeat(MAX_PATH_LENGTH / 2 + 1)), Err(PathError::TooLong));
+    }
+
+    #[test]
+    fn keys_ignore_case() {
+        assert_eq!(ClientPath::parse("/Folder/Save.bin").unwrap().key(), ClientPath::parse("folder/SAVE.BIN").unwrap().key());
+        assert_eq!(path_key("Folder/Save.bin"), path_key("/folder/save.bin"));
+        assert_ne!(path_key("/save.bin"), path_key("/save.bin.bak"));
+    }
+
+    #[test]
+    fn collisions_are_found() {
+        let entries = |entries: &[(&str, EntryKind)]| entries.iter().map(|(path, kind)| (ClientPath::parse(path).unwrap(), *kind)).collect::<Vec<(ClientPath, EntryKind)>>();
+        let pair = |a: &str, b: &str| (a.to_string(), b.to_string());
+        let (file, directory) = (EntryKind::FILE, EntryKind::DIRECTORY);
+
+        assert!(find_collisions(&entries(&[("/Save.bin", file), ("/Folder/Save.bin", file), ("/Save.bin", file), ("/Folder", directory)])).is_empty());
+        assert_eq!(find_collisions(&entries(&[("/Save.bin", file), ("/save.BIN", file)])), vec![pair("/Save.bin", "/save.BIN")]);
+        assert_eq!(find_collisions(&entries(&[("/data", file), ("/Data/a", file), ("/DATA/b", file)])), vec![pair("/data", "/Data/a"), pair("/data", "/DATA/b")]);
+        assert_eq!(find_collisions(&entries(&[("/Folder/a", file), ("/folder", file)])), vec![pair("/folder", "/Folder/a")]);
+        assert_eq!(find_collisions(&entries(&[("/Folder", directory), ("/Folder", file)])), vec![pair("/Folder", "/Folder")]);
+    }
+
+    #[test]
+    fn folders_are_implied() {
+        assert_eq!(folder_keys("/A/B/c.bin"), vec!["a".to_string(), "a/b".to_string()]);
+        assert!(folder_keys("c.bin").is_empty());
+
+        let listed = entry_keys([("/A/c.bin", EntryKind::FILE), ("/Empty", EntryKind::DIRECTORY)]);
+        let implied = entry_keys([("a/C.BIN", EntryKind::FILE), ("a", EntryKind::DIRECTORY), ("empty", EntryKind::DIRECTORY)]);
+        assert_eq!(listed, implied);
+        assert_eq!(listed.get("a"), Some(&EntryKind::DIRECTORY));
+    }
+
+    #[test]
+    fn joined_paths_stay_inside() {
+        let base = Path::new("/data/1/save");
+        let joined = ClientPath::parse("/Folder/./GameData.bin").unwrap().join_to(base);
+
+        assert_eq!(joined, Path::new("/data/1/save/Folder/GameData.bin"));
+        assert!(joined.starts_with(base));
+    }
+}
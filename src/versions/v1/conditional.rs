@@ -0,0 +1,206 @@
+use std::{io, pin::Pin, task::{Context, Poll}};
+
+use rocket::{Request, http::{ContentType, Status}, request::{FromRequest, Outcome}, response::{self, Responder, Response}, tokio::io::{AsyncRead, AsyncSeek, ReadBuf, Take}};
+
+use crate::versions::v1::storage::FileReader;
+
+// the conditional and range headers of a request, never fails
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Preconditions {
+    if_none_match: Option<String>,
+    if_range: Option<String>,
+    range: Option<String>
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Preconditions {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        Outcome::Success(Preconditions {
+            if_none_match: headers.get_one("If-None-Match").map(str::to_string),
+            if_range: headers.get_one("If-Range").map(str::to_string),
+            range: headers.get_one("Range").map(str::to_string)
+        })
+    }
+}
+
+fn quote(etag: &str) -> String {
+    format!("\"{etag}\"")
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ByteRange {
+    Full,
+    // inclusive start and end
+    Partial(u64, u64),
+    Unsatisfiable
+}
+
+impl Preconditions {
+    // true if the client already has the representation with this etag
+    pub fn not_modified(&self, etag: &str) -> bool {
+        let Some(header) = &self.if_none_match else { return false };
+        header.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == quote(etag))
+    }
+
+    // only single ranges are supported, anything else is answered with the whole file
+    pub fn range(&self, etag: &str, size: u64) -> ByteRange {
+        let Some(range) = self.range.as_deref().and_then(|range| range.trim().strip_prefix("bytes=")) else { return ByteRange::Full };
+        if self.if_range.as_deref().is_some_and(|tag| tag.trim() != quote(etag)) || range.contains(',') {
+            return ByteRange::Full
+        }
+
+        let Some((start, end)) = range.split_once('-') else { return ByteRange::Full };
+        let (start, end) = match (start.trim().parse::<u64>().ok(), end.trim().parse::<u64>().ok()) {
+            (Some(start), Some(end)) if start <= end => (start, end.min(size.saturating_sub(1))),
+            (Some(start), None) if end.trim().is_empty() => (start, size.saturating_sub(1)),
+            (None, Some(suffix)) if start.trim().is_empty() && suffix > 0 => (size.saturating_sub(suffix), size.saturating_sub(1)),
+            _ => return ByteRange::Full
+        };
+
+        if start >= size {
+            return ByteRange::Unsatisfiable
+        }
+
+        ByteRange::Partial(start, end)
+    }
+}
+
+// adds an ETag to a response, or answers 304 if the client's copy is current
+pub enum Conditional<R> {
+    NotModified(String),
+    Modified(String, R)
+}
+
+impl<R> Conditional<R> {
+    pub fn new(preconditions: &Preconditions, etag: String, body: R) -> Self {
+        match preconditions.not_modified(&etag) {
+            true => Conditional::NotModified(etag),
+            false => Conditional::Modified(etag, body)
+        }
+    }
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Conditional<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        match self {
+            Conditional::NotModified(etag) => Response::build().status(Status::NotModified).raw_header("ETag", quote(&etag)).ok(),
+            Conditional::Modified(etag, body) => Response::build_from(body.respond_to(request)?).raw_header("ETag", quote(&etag)).ok()
+        }
+    }
+}
+
+// the body of a partial response, rocket never seeks it because the size is preset
+struct RangeReader(Take<Box<dyn FileReader>>);
+
+impl AsyncRead for RangeReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for RangeReader {
+    fn start_seek(self: Pin<&mut Self>, _position: io::SeekFrom) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Range bodies can't be seeked"))
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Err(io::Error::new(io::ErrorKind::Unsupported, "Range bodies can't be seeked")))
+    }
+}
+
+pub enum FileBody {
+    Full(Box<dyn FileReader>, u64),
+    // the file has to be seeked to start already
+    Partial(Take<Box<dyn FileReader>>, u64, u64, u64),
+    Unsatisfiable(u64)
+}
+
+impl<'r> Responder<'r, 'static> for FileBody {
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            FileBody::Full(file, size) => Response::build()
+                .header(ContentType::Binary)
+                .raw_header("Accept-Ranges", "bytes")
+                .sized_body(size as usize, file)
+                .ok(),
+            FileBody::Partial(body, start, end, size) => Response::build()
+                .status(Status::PartialContent)
+                .header(ContentType::Binary)
+                .raw_header("Accept-Ranges", "bytes")
+                .raw_header("Content-Range", format!("bytes {start}-{end}/{size}"))
+                .sized_body((end - start + 1) as usize, RangeReader(body))
+                .ok(),
+            FileBody::Unsatisfiable(size) => Response::build()
+                .status(Status::RangeNotSatisfiable)
+                .raw_header("Content-Range", format!("bytes */{size}"))
+                .ok()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETAG: &str = "5d41402abc4b2a76b9719d911017c592";
+
+    fn range(range: &str, if_range: Option<&str>, size: u64) -> ByteRange {
+        Preconditions { range: Some(range.to_string()), if_range: if_range.map(str::to_string), ..Default::default() }.range(ETAG, size)
+    }
+
+    fn not_modified(if_none_match: &str) -> bool {
+        Preconditions { if_none_match: Some(if_none_match.to_string()), ..Default::default() }.not_modified(ETAG)
+    }
+
+    #[test]
+    fn ranges_are_clamped_to_the_file() {
+        assert_eq!(range("bytes=2-5", None, 10), ByteRange::Partial(2, 5));
+        assert_eq!(range("bytes=2-", None, 10), ByteRange::Partial(2, 9));
+        assert_eq!(range("bytes=0-100", None, 10), ByteRange::Partial(0, 9));
+        assert_eq!(range("bytes=10-", None, 10), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn suffix_ranges_count_from_the_end() {
+        assert_eq!(range("bytes=-3", None, 10), ByteRange::Partial(7, 9));
+        assert_eq!(range("bytes=-20", None, 10), ByteRange::Partial(0, 9));
+        assert_eq!(range("bytes=-0", None, 10), ByteRange::Full);
+    }
+
+    #[test]
+    fn invalid_ranges_send_the_whole_file() {
+        assert_eq!(range("bytes=5-2", None, 10), ByteRange::Full);
+        assert_eq!(range("bytes=0-1,4-5", None, 10), ByteRange::Full);
+        assert_eq!(range("bytes=a-b", None, 10), ByteRange::Full);
+        assert_eq!(range("items=0-1", None, 10), ByteRange::Full);
+        assert_eq!(Preconditions::default().range(ETAG, 10), ByteRange::Full);
+    }
+
+    #[test]
+    fn empty_files_have_no_satisfiable_range() {
+        assert_eq!(range("bytes=0-", None, 0), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-5", None, 0), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=0-0", None, 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn if_range_needs_the_same_strong_etag() {
+        assert_eq!(range("bytes=2-5", Some(&quote(ETAG)), 10), ByteRange::Partial(2, 5));
+        assert_eq!(range("bytes=2-5", Some("\"other\""), 10), ByteRange::Full);
+        assert_eq!(range("bytes=2-5", Some(&format!("W/{}", quote(ETAG))), 10), ByteRange::Full);
+    }
+
+    #[test]
+    fn if_none_match_accepts_weak_tags_and_any() {
+        assert!(not_modified(&quote(ETAG)));
+        assert!(not_modified(&format!("W/{}", quote(ETAG))));
+        assert!(not_modified(&format!("\"other\", {}", quote(ETAG))));
+        assert!(not_modified("*"));
+        assert!(!not_modified("\"other\""));
+        assert!(!not_modified(ETAG));
+        assert!(!Preconditions::default().not_modified(ETAG));
+    }
+}
@@ -1,89 +1,146 @@
-use std::{collections::HashMap, fs, path::Path, str::FromStr};
+use std::{collections::HashMap, io::ErrorKind, str::FromStr};
 
-use fs_extra::dir::{self, get_dir_content2};
-use rocket::{State, http::Status, serde::{Deserialize, json::Json}};
+use rocket::{State, serde::{Deserialize, json::Json}};
 use serde::Serialize;
-use uuid::Uuid;
-use crate::{config::Config, v1::ticket::{Container, Ticket, TicketType, Tickets}, versions::v1::{file_info::{ClientFileInfo, DownloadAction, DownloadFileInfo, file_hash}, ticket::{copy_dir_all, ticket_path, tickets_path}}};
+use crate::{config::Config, devices::Device, error::ApiError, v1::ticket::{Container, Ticket, TicketType, Tickets}, versions::v1::{Synced, client_path::{ClientPath, entry_keys, path_key}, file_info::{ClientFileInfo, DownloadAction, DownloadFileInfo, EntryKind, HashAlgorithm, hashes_match}, locks::TitleLocks, storage::{Store, blocking}, ticket::save_ticket}};
 
 #[derive(Debug, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BeginBody {
     id: u64,
     container: String,
-    existing_files: Vec<ClientFileInfo>
+    existing_files: Vec<ClientFileInfo>,
+    #[serde(default)]
+    revision: Option<u64>,
+    #[serde(default)]
+    algorithm: HashAlgorithm
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct BeginResponse {
     ticket: String,
-    files: Vec<DownloadFileInfo>
+    files: Vec<DownloadFileInfo>,
+    // the revision being downloaded, sent back as the base revision of the next upload
+    revision: Option<u64>,
+    algorithm: HashAlgorithm
 }
 
-#[post("/v1/download/begin", format = "application/json", data = "<data>")]
-pub fn download_begin(tickets: &State<Tickets>, config: &State<Config>, data: Json<BeginBody>) -> Result<Json<BeginResponse>, Status> {
-    let container = Container::from_str(&data.container).map_err(|_| Status::BadRequest)?;
-    let mut ticket_map = tickets.lock().map_err(|_| Status::InternalServerError)?;
+#[derive(Debug, Responder)]
+pub enum BeginResult {
+    Started(Json<BeginResponse>),
+    UpToDate(Synced)
+}
 
-    let title_path = config.data_directory().join(format!("{:X}", data.id));
-    let container_path = title_path.join(container.to_string().to_lowercase());
-    let container_path_str = container_path.to_str().expect("Failed to get string of container path");
+#[post("/v1/download/begin", format = "application/json", data = "<data>")]
+pub async fn download_begin(_device: Device, tickets: &State<Tickets>, locks: &State<TitleLocks>, config: &State<Config>, storage: &State<Store>, data: Json<BeginBody>) -> Result<BeginResult, ApiError> {
+    let container = Container::from_str(&data.container).map_err(|_| ApiError::BadRequest(format!("{} isn't a container", data.container)))?;
 
-    if !container_path.exists() {
-        return Err(Status::NoContent)
+    let mut existing_files = Vec::new();
+    for file in &data.existing_files {
+        let path = ClientPath::parse(&file.path).map_err(|err| ApiError::InvalidPath(file.path.clone(), err))?;
+        existing_files.push(ClientFileInfo { path: path.to_string(), ..file.clone() });
     }
-    
-    let ticket_id = Uuid::new_v4();
-    let ticket = Ticket { id: ticket_id, title_id: data.id, kind: TicketType::DOWNLOAD, container: container };
 
-    let base_staging_path = tickets_path();
-    let staging_path = ticket_path(ticket_id);
+    let mut ticket = Ticket::new(data.id, TicketType::DOWNLOAD, container, data.revision, data.algorithm);
+    let ticket_id = ticket.id;
 
-    if !base_staging_path.exists() && fs::create_dir_all(&base_staging_path).is_err() {
-        return Err(Status::InternalServerError)
+    // nothing can be committed while the title is read, so the snapshot is exactly the revision returned
+    let _title = locks.read(data.id, container).await;
+    let revision = match data.revision {
+        Some(revision) => Some(revision),
+        None => {
+            let id = data.id;
+            blocking(storage, move |storage| storage.latest_revision(id, container)).await?.map(|revision| revision.id)
+        }
+    };
+
+    // a missing container has nothing to download, but a missing revision can't be downloaded at all
+    let snapshot = ticket.clone();
+    match blocking(storage, move |storage| storage.begin_transaction(&snapshot)).await {
+        Err(err) if err.kind() == ErrorKind::NotFound => match data.revision {
+            Some(revision) => return Err(ApiError::NotFound(format!("Revision {revision} of {:X} doesn't exist", data.id))),
+            None => return Ok(BeginResult::UpToDate(Synced(None)))
+        },
+        Err(err) => return Err(err.into()),
+        Ok(()) => {}
     }
 
-    copy_dir_all(&container_path, &staging_path).expect("Failed to copy container path to staging path");
-    ticket_map.insert(ticket_id, ticket);
-    drop(ticket_map);
-
-    let mut actions: HashMap<String, DownloadFileInfo> = data.existing_files
+    // keyed case insensitively, a file the client has in another case is the same file
+    let mut actions: HashMap<String, DownloadFileInfo> = existing_files
         .iter()
-        .map(|f| ( f.path.clone(), DownloadFileInfo{ action: DownloadAction::REMOVE, path: f.path.clone(), hash: f.hash.clone(), size: Some(f.size) } ))
+        .map(|f| ( path_key(&f.path), DownloadFileInfo{ action: DownloadAction::REMOVE, path: f.path.clone(), hash: f.hash.clone().filter(|_| f.kind == EntryKind::FILE), size: Some(f.size).filter(|_| f.kind == EntryKind::FILE), kind: f.kind, mtime: f.mtime } ))
         .collect();
 
-    let contents = get_dir_content2(&container_path, &dir::DirOptions::new()).expect("Failed to get staging path contents");
-    for path in contents.files {
-        let Some(file) = path.strip_prefix(container_path_str) else { continue; };
-        let Ok(metadata) = fs::metadata(&path) else { continue; };
-
-        let size = metadata.len();
-        let Ok(hash) = file_hash(Path::new(&path)) else { continue; };
-
-        if let Some(info) = actions.get_mut(file) {
-            if info.size == Some(size) && info.hash == Some(hash.clone()) {
+    // the snapshot can't change anymore, so the etags of its files are known up front
+    let (id, requested, algorithm) = (data.id, data.revision, data.algorithm);
+    let (manifest, md5_manifest) = blocking(storage, move |storage| {
+        let manifest = storage.read_manifest(id, container, requested, algorithm)?.unwrap_or_default();
+        let md5_manifest = match algorithm {
+            HashAlgorithm::MD5 => manifest.clone(),
+            _ => storage.read_manifest(id, container, requested, HashAlgorithm::MD5)?.unwrap_or_default()
+        };
+
+        Ok((manifest, md5_manifest))
+    }).await?;
+    ticket.etags = md5_manifest.into_iter().filter(|file| file.kind == EntryKind::FILE).map(|file| (path_key(&file.path), file.hash)).collect();
+    // files are sent under the servers spelling, whatever case the client asks for them in
+    ticket.manifest = Some(manifest.iter().map(|file| ClientFileInfo { path: format!("/{}", file.path), size: file.size, hash: Some(file.hash.clone()), kind: file.kind, mtime: file.mtime }).collect());
+
+    save_ticket(config, &ticket)?;
+    tickets.lock()?.insert(ticket_id, ticket);
+
+    let server_entries = entry_keys(manifest.iter().map(|file| (file.path.as_str(), file.kind)));
+    for file in manifest {
+        let path = format!("/{}", file.path);
+        let (size, hash) = match file.kind {
+            EntryKind::FILE => (Some(file.size), Some(file.hash)),
+            EntryKind::DIRECTORY => (None, None)
+        };
+
+        if let Some(info) = actions.get_mut(&path_key(&path)) {
+            // kept files still get the servers mtime, so the client can update its own
+            let same_hash = match (info.hash.as_deref(), hash.as_deref()) {
+                (Some(client), Some(server)) => hashes_match(client, server),
+                (client, server) => client == server
+            };
+
+            if info.kind == file.kind && info.size == size && same_hash {
                 info.action = DownloadAction::KEEP;
+                info.mtime = file.mtime.or(info.mtime);
                 continue;
             }
 
+            // downloaded under the servers spelling
             info.action = DownloadAction::REPLACE;
-            info.size = Some(size);
-            info.hash = Some(hash);
+            info.path = path;
+            info.size = size;
+            info.hash = hash;
+            info.kind = file.kind;
+            info.mtime = file.mtime;
 
             continue;
         }
 
-        actions.insert(file.to_string(), DownloadFileInfo {
-            path: file.to_string(),
-            size: Some(size),
-            hash: Some(hash),
-            action: DownloadAction::CREATE
+        actions.insert(path_key(&path), DownloadFileInfo {
+            path,
+            size,
+            hash,
+            action: DownloadAction::CREATE,
+            kind: file.kind,
+            mtime: file.mtime
         });
     }
 
+    // folders that aren't empty on the server are only implied by their contents
+    for (key, info) in actions.iter_mut() {
+        if info.action == DownloadAction::REMOVE && info.kind == EntryKind::DIRECTORY && server_entries.get(key) == Some(&EntryKind::DIRECTORY) {
+            info.action = DownloadAction::KEEP;
+        }
+    }
+
     if actions.iter().all(|f| f.1.action == DownloadAction::KEEP) {
-        return Err(Status::NoContent)
+        return Ok(BeginResult::UpToDate(Synced(revision)))
     }
 
-    Ok(Json(BeginResponse{ ticket: ticket_id.hyphenated().to_string(), files: actions.iter().map(|action| action.1.clone()).collect() }))
+    Ok(BeginResult::Started(Json(BeginResponse{ ticket: ticket_id.hyphenated().to_string(), files: actions.iter().map(|action| action.1.clone()).collect(), revision, algorithm: data.algorithm })))
 }
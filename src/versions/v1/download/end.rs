@@ -1,22 +1,28 @@
 use rocket::{State, http::Status};
 use uuid::Uuid;
 
-use crate::versions::v1::ticket::{TicketType, Tickets, clear_ticket_path};
+use crate::{config::Config, devices::Device, error::ApiError, versions::v1::{storage::Store, ticket::{TicketType, Tickets, delete_ticket}}};
 
 #[delete("/v1/download/<ticket>")]
-pub fn download_end(tickets: &State<Tickets>, ticket: &str) -> Result<Status, Status> {
-    let uuid = Uuid::try_parse(&ticket).map_err(|_| Status::Forbidden)?;
-    let mut ticket_map = tickets.lock().map_err(|_| Status::InternalServerError)?;
+pub fn download_end(_device: Device, tickets: &State<Tickets>, config: &State<Config>, storage: &State<Store>, ticket: &str) -> Result<Status, ApiError> {
+    let uuid = Uuid::try_parse(ticket).map_err(|_| ApiError::MalformedTicket(ticket.to_string()))?;
+    let mut ticket_map = tickets.lock()?;
     
-    let Some(ticket) = ticket_map.get(&uuid).cloned() else { return Err(Status::BadRequest) };
+    let Some(ticket) = ticket_map.get(&uuid).cloned() else { return Err(ApiError::UnknownTicket(ticket.to_string())) };
     if ticket.kind != TicketType::DOWNLOAD {
-        return Ok(Status::BadRequest)
+        return Err(ApiError::WrongTicketType(ticket.id.hyphenated().to_string()))
     }
+
+    ticket_map.remove(&uuid);
+    drop(ticket_map);
     
-    if clear_ticket_path(uuid).is_err() {
+    if storage.abort_transaction(uuid).is_err() {
         println!("Failed to clear ticket path {}", ticket.id.hyphenated());
     }
+
+    if delete_ticket(config, uuid).is_err() {
+        println!("Failed to delete ticket {}", ticket.id.hyphenated());
+    }
     
-    ticket_map.remove(&uuid);
     Ok(Status::NoContent)
 }
\ No newline at end of file
@@ -1,29 +1,53 @@
-use std::fs;
+use std::io::{ErrorKind, SeekFrom};
 
-use rocket::{State, http::Status};
+use rocket::{State, tokio::io::{AsyncReadExt, AsyncSeekExt}};
 use uuid::Uuid;
-use crate::{v1::ticket::Tickets, versions::v1::ticket::{TicketType, ticket_path}};
+use crate::{devices::Device, error::ApiError, v1::ticket::Tickets, versions::v1::{client_path::ClientPath, conditional::{ByteRange, Conditional, FileBody, Preconditions}, file_info::HashAlgorithm, storage::{Store, blocking}, ticket::TicketType}};
 
 #[get("/v1/download/<ticket>/file?<path>", format = "application/octet-stream")]
-pub fn download_file(tickets: &State<Tickets>, ticket: &str, path: &str) -> Result<Vec<u8>, Status> {
-    let uuid = Uuid::try_parse(&ticket).map_err(|_| Status::Forbidden)?;
-    let ticket_map = tickets.lock().map_err(|_| Status::InternalServerError)?;
+pub async fn download_file(_device: Device, tickets: &State<Tickets>, storage: &State<Store>, preconditions: Preconditions, ticket: &str, path: &str) -> Result<Conditional<FileBody>, ApiError> {
+    let uuid = Uuid::try_parse(ticket).map_err(|_| ApiError::MalformedTicket(ticket.to_string()))?;
+    let client_path = ClientPath::parse(path).map_err(|err| ApiError::InvalidPath(path.to_string(), err))?;
 
-    let Some(ticket) = ticket_map.get(&uuid).cloned() else { return Err(Status::BadRequest) };
-    if ticket.kind != TicketType::DOWNLOAD {
-        return Err(Status::BadRequest)
-    }
+    let (path, etag) = {
+        let mut ticket_map = tickets.lock()?;
 
-    let base_path = ticket_path(uuid);
-    let file_path = base_path.join(path.strip_prefix("/").unwrap_or(path));
-    if !file_path.starts_with(base_path) {
-        return Err(Status::BadRequest)
-    }
+        let Some(ticket) = ticket_map.get_mut(&uuid) else { return Err(ApiError::UnknownTicket(ticket.to_string())) };
+        if ticket.kind != TicketType::DOWNLOAD {
+            return Err(ApiError::WrongTicketType(ticket.id.hyphenated().to_string()))
+        }
+
+        ticket.touch();
+        // opened as the server spells it, whatever case the client used here
+        let Some(path) = ticket.manifest_path(&client_path) else { return Err(ApiError::NotFound(format!("{client_path} isn't part of the download"))) };
+        (path, ticket.etags.get(&client_path.key()).cloned())
+    };
 
-    let metadata = fs::metadata(&file_path).map_err(|_| Status::Forbidden)?;
-    if !metadata.is_file() {
-        return Err(Status::Forbidden)
+    let (mut file, size) = storage.open_file(uuid, &path).await.map_err(|err| match err.kind() {
+        ErrorKind::NotFound => ApiError::NotFound(format!("{client_path} isn't part of the download")),
+        _ => err.into()
+    })?;
+
+    // tickets from before etags were kept have to hash the file
+    let etag = match etag {
+        Some(etag) => etag,
+        None => {
+            let path = path.clone();
+            blocking(storage, move |storage| storage.hash_file(uuid, &path, HashAlgorithm::MD5)).await?
+        }
+    };
+    if preconditions.not_modified(&etag) {
+        return Ok(Conditional::NotModified(etag))
     }
-    
-    Ok(fs::read(&file_path).map_err(|_| Status::InternalServerError)?)
+
+    let body = match preconditions.range(&etag, size) {
+        ByteRange::Full => FileBody::Full(file, size),
+        ByteRange::Partial(start, end) => {
+            file.seek(SeekFrom::Start(start)).await?;
+            FileBody::Partial(file.take(end - start + 1), start, end, size)
+        },
+        ByteRange::Unsatisfiable => FileBody::Unsatisfiable(size)
+    };
+
+    Ok(Conditional::Modified(etag, body))
 }
@@ -1,19 +1,111 @@
 use std::{fs, io::{self, Read}, path::Path};
 
+use fs_extra::dir::get_dir_content;
 use rocket::serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+use crate::versions::v1::hash_index::FileIndex;
+
+// what the hash of a manifest means, clients pick one from the list advertised by /v1/status
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default, Serialize, Deserialize, FromFormField)]
+pub enum HashAlgorithm {
+    #[default]
+    #[serde(alias = "md5")]
+    MD5,
+    #[serde(alias = "sha256")]
+    SHA256,
+    #[serde(alias = "crc32")]
+    CRC32
+}
+
+pub const HASH_ALGORITHMS: [HashAlgorithm; 3] = [HashAlgorithm::MD5, HashAlgorithm::SHA256, HashAlgorithm::CRC32];
+
+impl std::fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HashAlgorithm::MD5    => write!(f, "MD5"),
+            HashAlgorithm::SHA256 => write!(f, "SHA256"),
+            HashAlgorithm::CRC32  => write!(f, "CRC32")
+        }
+    }
+}
+
+pub enum Hasher {
+    MD5(md5::Context),
+    SHA256(Sha256),
+    CRC32(crc32fast::Hasher)
+}
+
+impl Hasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::MD5    => Hasher::MD5(md5::Context::new()),
+            HashAlgorithm::SHA256 => Hasher::SHA256(Sha256::new()),
+            HashAlgorithm::CRC32  => Hasher::CRC32(crc32fast::Hasher::new())
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::MD5(context)    => context.consume(data),
+            Hasher::SHA256(context) => context.update(data),
+            Hasher::CRC32(context)  => context.update(data)
+        }
+    }
+
+    // lowercase hex, crc32 is padded to 8 digits
+    pub fn finalize(self) -> String {
+        match self {
+            Hasher::MD5(context)    => format!("{:x}", context.finalize()),
+            Hasher::SHA256(context) => context.finalize().iter().map(|byte| format!("{byte:02x}")).collect(),
+            Hasher::CRC32(context)  => format!("{:08x}", context.finalize())
+        }
+    }
+}
+
+// the server always sends lowercase hex, clients may send either case
+pub fn hashes_match(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+// directories are listed so empty ones survive a sync, they have no size or hash
+// directories that have something in them are implied by their contents, servers only list empty ones
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum EntryKind {
+    #[default]
+    FILE,
+    DIRECTORY
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
 pub struct ClientFileInfo {
     pub path: String,
     pub size: u64,
-    pub hash: Option<String>
+    pub hash: Option<String>,
+    #[serde(default)]
+    pub kind: EntryKind,
+    // seconds since the unix epoch, when the file was last written on the client
+    #[serde(default)]
+    pub mtime: Option<u64>
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+// directories have a size of 0 and an empty hash
+// the mtime is the one the uploading client declared, the files on disk are links to staged uploads or restored copies so theirs say nothing about the client
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
 pub struct ServerFileInfo {
     pub path: String,
     pub size: u64,
-    pub hash: String
+    pub hash: String,
+    #[serde(default)]
+    pub kind: EntryKind,
+    #[serde(default)]
+    pub mtime: Option<u64>
+}
+
+impl ServerFileInfo {
+    pub fn directory(path: String) -> Self {
+        ServerFileInfo { path, size: 0, hash: String::new(), kind: EntryKind::DIRECTORY, mtime: None }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
@@ -29,24 +121,53 @@ pub struct DownloadFileInfo {
     pub path: String,
     pub size: Option<u64>,
     pub hash: Option<String>,
-    pub action: DownloadAction
+    pub action: DownloadAction,
+    pub kind: EntryKind,
+    pub mtime: Option<u64>
 }
 
-pub fn file_hash(path: &Path) -> std::io::Result<String> {
+pub fn file_hash(path: &Path, algorithm: HashAlgorithm) -> std::io::Result<String> {
     let file = fs::File::open(path)?;
 
-    let mut context = md5::Context::new();
+    let mut context = Hasher::new(algorithm);
     let mut buffer = [0; 4096];
     let mut reader = io::BufReader::new(file);
 
     loop {
-        let bytes_read = reader.read(&mut buffer).expect("Failed to get bytes from file");
+        let bytes_read = reader.read(&mut buffer)?;
         if bytes_read == 0 {
             break;
         }
 
-        context.consume(&buffer[..bytes_read]);
+        context.update(&buffer[..bytes_read]);
     }
 
-    Ok(format!("{:x}", context.finalize()))
+    Ok(context.finalize())
+}
+
+pub fn get_dir_info(dir: String, algorithm: HashAlgorithm, index: &FileIndex) -> io::Result<Vec<ServerFileInfo>> {
+    let path = Path::new(&dir).to_path_buf();
+    let mut out: Vec<ServerFileInfo> = Vec::new();
+
+    if path.try_exists().unwrap_or(false) {
+        let content = get_dir_content(path).map_err(io::Error::other)?;
+        for file in content.files {
+            let Some(path) = file.strip_prefix(&dir) else { continue; };
+            let Ok(metadata) = fs::metadata(&file) else { continue; };
+            let Ok(hash) = index.hash(Path::new(&file), algorithm) else { continue; };
+
+            out.push(ServerFileInfo { path: path.to_string(), size: metadata.len(), hash, kind: EntryKind::FILE, mtime: None });
+        }
+
+        for directory in content.directories {
+            let Some(path) = directory.strip_prefix(&dir).filter(|path| !path.is_empty()) else { continue; };
+            if fs::read_dir(&directory).map(|mut entries| entries.next().is_none()).unwrap_or(false) {
+                out.push(ServerFileInfo::directory(path.to_string()));
+            }
+        }
+    }
+
+    out.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(out)
 }
\ No newline at end of file
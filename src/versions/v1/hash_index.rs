@@ -0,0 +1,149 @@
+use std::{collections::HashMap, fs::{self, File, Metadata}, io::{self, BufReader}, path::{Path, PathBuf}, sync::{Arc, Mutex, MutexGuard}, time::UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, versions::v1::file_info::{HashAlgorithm, file_hash}};
+
+// hashes of the files in the data directory, kept in <data_directory>/.index.json
+// an entry is trusted as long as the size and mtime of its file haven't changed
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+struct IndexEntry {
+    size: u64,
+    modified: u128,
+    hashes: HashMap<HashAlgorithm, String>
+}
+
+#[derive(Debug, Default)]
+struct IndexState {
+    // keyed by the path relative to the data directory
+    entries: HashMap<String, IndexEntry>,
+    dirty: bool
+}
+
+// the lock is only held to read or write entries and never while a file is hashed, so hashing one title doesn't hold up the others
+#[derive(Debug, Default)]
+pub struct FileIndex {
+    root: PathBuf,
+    state: Mutex<IndexState>
+}
+
+pub type HashIndex = Arc<FileIndex>;
+
+fn index_path(config: &Config) -> PathBuf {
+    config.data_directory().join(".index.json")
+}
+
+fn modified_nanos(metadata: &Metadata) -> u128 {
+    metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|time| time.as_nanos()).unwrap_or(0)
+}
+
+impl FileIndex {
+    // entries of files that were removed while the server was down are dropped
+    pub fn load(config: &Config) -> Self {
+        let entries: HashMap<String, IndexEntry> = File::open(index_path(config))
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default();
+
+        let root = config.data_directory();
+        let count = entries.len();
+        let entries: HashMap<String, IndexEntry> = entries.into_iter().filter(|(path, _)| root.join(path).is_file()).collect();
+        let dirty = entries.len() != count;
+
+        FileIndex { root, state: Mutex::new(IndexState { entries, dirty }) }
+    }
+
+    fn state(&self) -> io::Result<MutexGuard<'_, IndexState>> {
+        self.state.lock().map_err(|_| io::Error::other("File index lock is poisoned"))
+    }
+
+    // only writes the index if it changed since the last save
+    pub fn save(&self, config: &Config) -> io::Result<()> {
+        let mut state = self.state()?;
+        if !state.dirty {
+            return Ok(())
+        }
+
+        fs::create_dir_all(config.data_directory())?;
+
+        let temp_path = index_path(config).with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_string(&state.entries).map_err(io::Error::other)?)?;
+        fs::rename(temp_path, index_path(config))?;
+
+        state.dirty = false;
+        Ok(())
+    }
+
+    fn key(&self, path: &Path) -> Option<String> {
+        path.strip_prefix(&self.root).ok().and_then(Path::to_str).map(str::to_string)
+    }
+
+    // files outside the data directory are hashed every time
+    pub fn hash(&self, path: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
+        let Some(key) = self.key(path) else { return file_hash(path, algorithm) };
+
+        let metadata = fs::metadata(path)?;
+        let (size, modified) = (metadata.len(), modified_nanos(&metadata));
+
+        if let Some(entry) = self.state()?.entries.get(&key).filter(|entry| entry.size == size && entry.modified == modified) {
+            if let Some(hash) = entry.hashes.get(&algorithm) {
+                return Ok(hash.clone())
+            }
+        }
+
+        let hash = file_hash(path, algorithm)?;
+
+        let mut state = self.state()?;
+        let entry = state.entries.entry(key).or_insert_with(|| IndexEntry { size, modified, hashes: HashMap::new() });
+        if entry.size != size || entry.modified != modified {
+            *entry = IndexEntry { size, modified, hashes: HashMap::new() };
+        }
+
+        entry.hashes.insert(algorithm, hash.clone());
+        state.dirty = true;
+
+        Ok(hash)
+    }
+
+    // records a hash that is already known, e.g. from a verified upload
+    pub fn insert(&self, path: &Path, algorithm: HashAlgorithm, hash: String) {
+        let Some(key) = self.key(path) else { return };
+        let Ok(metadata) = fs::metadata(path) else { return };
+        let Ok(mut state) = self.state() else { return };
+
+        let mut hashes = HashMap::new();
+        hashes.insert(algorithm, hash);
+
+        state.entries.insert(key, IndexEntry { size: metadata.len(), modified: modified_nanos(&metadata), hashes });
+        state.dirty = true;
+    }
+
+    // moves the entries under from to the same paths under to, for a directory that was renamed over to
+    pub fn rename_dir(&self, from: &Path, to: &Path) {
+        let (Some(from), Some(to)) = (self.key(from), self.key(to)) else { return };
+        let Ok(mut state) = self.state() else { return };
+
+        state.entries.retain(|path, _| !Path::new(path).starts_with(&to));
+        let moved: Vec<String> = state.entries.keys().filter(|path| Path::new(path).starts_with(&from)).cloned().collect();
+        for path in moved {
+            let Some(entry) = state.entries.remove(&path) else { continue; };
+            let Some(renamed) = Path::new(&path).strip_prefix(&from).ok().and_then(|relative| Path::new(&to).join(relative).to_str().map(str::to_string)) else { continue; };
+            state.entries.insert(renamed, entry);
+        }
+
+        state.dirty = true;
+    }
+
+    // drops the entries under a directory whose files no longer exist
+    pub fn prune(&self, dir: &Path) {
+        let Some(prefix) = self.key(dir) else { return };
+        let Ok(mut state) = self.state() else { return };
+
+        let count = state.entries.len();
+        state.entries.retain(|path, _| !Path::new(path).starts_with(&prefix) || self.root.join(path).is_file());
+
+        if state.entries.len() != count {
+            state.dirty = true;
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use std::{collections::{BTreeMap, HashMap}, fs::{self, File}, io::{self, BufReader}, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, versions::v1::{blobs::{copy_blob, link_blob, store_blob}, client_path::path_key, file_info::{EntryKind, HashAlgorithm, ServerFileInfo, get_dir_info}, hash_index::FileIndex, storage::filesystem::{sync_dir, write_synced}, ticket::Container}};
+
+// every committed upload is kept as an immutable revision under:
+// <data_directory>/<TITLEID>/history/<container>/<revision id>/revision.json
+// revisions that were replaced by a forced upload are listed in <data_directory>/<TITLEID>/history/<container>/conflicts.json
+// the file contents are in the blob store, see blobs.rs
+// the live <data_directory>/<TITLEID>/<container> directory always mirrors the newest revision
+
+// revision manifests always use MD5 hashes
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+pub struct Revision {
+    pub id: u64,
+    pub timestamp: u64,
+    pub uploader: String,
+    #[serde(default)]
+    pub restored_from: Option<u64>,
+    pub files: Vec<ServerFileInfo>,
+    // file path to the SHA-256 of its blob
+    #[serde(default)]
+    pub blobs: BTreeMap<String, String>
+}
+
+impl Revision {
+    // by path key, for entries that have one
+    pub fn mtimes(&self) -> HashMap<String, u64> {
+        self.files.iter().filter_map(|file| Some((path_key(&file.path), file.mtime?))).collect()
+    }
+}
+
+// entries without a declared mtime keep the one from the previous revision, as long as they didn't change
+pub fn set_mtimes(files: &mut [ServerFileInfo], mtimes: &HashMap<String, u64>, previous: Option<&Revision>) {
+    for file in files.iter_mut() {
+        let key = path_key(&file.path);
+        file.mtime = mtimes.get(&key).copied().or_else(|| {
+            previous?.files.iter().find(|old| path_key(&old.path) == key && old.kind == file.kind && old.hash == file.hash)?.mtime
+        });
+    }
+}
+
+// a revision that lost a conflict, kept until someone picks it by restoring it
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+pub struct ConflictRevision {
+    pub name: String,
+    pub container: Container,
+    pub revision: u64,
+    // the device that uploaded the losing revision and when
+    pub device: String,
+    pub timestamp: u64,
+    // the device whose upload was forced through
+    pub replaced_by: String,
+    pub files: Vec<ServerFileInfo>
+}
+
+impl ConflictRevision {
+    pub fn new(container: Container, revision: &Revision, replaced_by: &str) -> Self {
+        ConflictRevision {
+            name: format!("{} conflict {}", revision.uploader, revision.timestamp),
+            container,
+            revision: revision.id,
+            device: revision.uploader.clone(),
+            timestamp: revision.timestamp,
+            replaced_by: replaced_by.to_string(),
+            files: revision.files.clone()
+        }
+    }
+}
+
+pub fn history_path(config: &Config, title_id: u64, container: Container) -> PathBuf {
+    config.data_directory().join(format!("{:X}", title_id)).join("history").join(container.to_string().to_lowercase())
+}
+
+pub fn revision_path(config: &Config, title_id: u64, container: Container, revision: u64) -> PathBuf {
+    history_path(config, title_id, container).join(revision.to_string())
+}
+
+// the id of the newest revision, so it can be found without reading the whole history
+fn latest_path(config: &Config, title_id: u64, container: Container) -> PathBuf {
+    history_path(config, title_id, container).join("latest")
+}
+
+fn write_latest(config: &Config, title_id: u64, container: Container, revision: u64) -> io::Result<()> {
+    write_synced(&latest_path(config, title_id, container), revision.to_string().as_bytes())?;
+    sync_dir(&history_path(config, title_id, container))
+}
+
+// where revisions from before the blob store kept their files
+fn legacy_files_path(config: &Config, title_id: u64, container: Container, revision: u64) -> PathBuf {
+    revision_path(config, title_id, container, revision).join("files")
+}
+
+// every title with a directory in the data directory
+pub fn title_ids(config: &Config) -> Vec<u64> {
+    let Ok(entries) = fs::read_dir(config.data_directory()) else { return Vec::new() };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        .filter_map(|entry| entry.file_name().to_str().and_then(|name| u64::from_str_radix(name, 16).ok()))
+        .collect()
+}
+
+fn read_revision(path: PathBuf) -> io::Result<Revision> {
+    let file = File::open(path.join("revision.json"))?;
+    serde_json::from_reader(BufReader::new(file)).map_err(io::Error::other)
+}
+
+pub fn get_revision(config: &Config, title_id: u64, container: Container, revision: u64) -> io::Result<Revision> {
+    read_revision(revision_path(config, title_id, container, revision))
+}
+
+// sorted oldest first, revisions without a readable revision.json (e.g. an interrupted commit) are skipped
+pub fn list_revisions(config: &Config, title_id: u64, container: Container) -> io::Result<Vec<Revision>> {
+    read_revisions(config, title_id, container, false)
+}
+
+// reads only the revision the latest pointer refers to, histories without one are listed instead
+pub fn latest_revision(config: &Config, title_id: u64, container: Container) -> io::Result<Option<Revision>> {
+    let pointer = fs::read_to_string(latest_path(config, title_id, container)).ok().and_then(|id| id.trim().parse::<u64>().ok());
+    if let Some(id) = pointer {
+        match get_revision(config, title_id, container, id) {
+            Ok(revision) => return Ok(Some(revision)),
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            Err(_) => {}
+        }
+    }
+
+    Ok(list_revisions(config, title_id, container)?.pop())
+}
+
+// like list_revisions, but a revision.json that exists and can't be read is an error instead of being skipped
+// only revisions that were never finished, which have no revision.json yet, are left out
+pub fn list_revisions_strict(config: &Config, title_id: u64, container: Container) -> io::Result<Vec<Revision>> {
+    read_revisions(config, title_id, container, true)
+}
+
+fn read_revisions(config: &Config, title_id: u64, container: Container, strict: bool) -> io::Result<Vec<Revision>> {
+    let path = history_path(config, title_id, container);
+    if !path.exists() {
+        return Ok(Vec::new())
+    }
+
+    let mut out: Vec<Revision> = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_name().to_str().and_then(|name| name.parse::<u64>().ok()).is_none() {
+            continue;
+        }
+
+        match read_revision(entry.path()) {
+            Ok(revision) => out.push(revision),
+            Err(err) if strict && err.kind() != io::ErrorKind::NotFound => {
+                return Err(io::Error::new(err.kind(), format!("{} can't be read: {err}", entry.path().display())))
+            },
+            Err(_) => continue
+        }
+    }
+
+    out.sort_by_key(|revision| revision.id);
+    Ok(out)
+}
+
+fn conflicts_path(config: &Config, title_id: u64, container: Container) -> PathBuf {
+    history_path(config, title_id, container).join("conflicts.json")
+}
+
+pub fn list_conflicts(config: &Config, title_id: u64, container: Container) -> io::Result<Vec<ConflictRevision>> {
+    let file = match File::open(conflicts_path(config, title_id, container)) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err)
+    };
+
+    serde_json::from_reader(BufReader::new(file)).map_err(io::Error::other)
+}
+
+fn write_conflicts(config: &Config, title_id: u64, container: Container, conflicts: &[ConflictRevision]) -> io::Result<()> {
+    let path = conflicts_path(config, title_id, container);
+    if conflicts.is_empty() {
+        return match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(())
+        }
+    }
+
+    fs::create_dir_all(history_path(config, title_id, container))?;
+
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_string_pretty(conflicts).map_err(io::Error::other)?)?;
+    fs::rename(temp_path, path)
+}
+
+pub fn add_conflict(config: &Config, title_id: u64, container: Container, conflict: ConflictRevision) -> io::Result<()> {
+    let mut conflicts = list_conflicts(config, title_id, container)?;
+    conflicts.push(conflict);
+
+    write_conflicts(config, title_id, container, &conflicts)
+}
+
+// where a commit keeps its revision until it's finished, revisions are only listed once they have a revision.json
+fn pending_revision_path(config: &Config, title_id: u64, container: Container, revision: u64) -> PathBuf {
+    revision_path(config, title_id, container, revision).join("revision.pending.json")
+}
+
+// stores the files of dir as the next revision of the container, with the mtimes the client declared by path key
+// the revision stays pending until publish_revision, so a commit that never finishes never shows up in the history
+#[allow(clippy::too_many_arguments)]
+pub fn prepare_revision(config: &Config, index: &FileIndex, title_id: u64, container: Container, dir: &Path, uploader: &str, restored_from: Option<u64>, mtimes: &HashMap<String, u64>) -> io::Result<Revision> {
+    fs::create_dir_all(history_path(config, title_id, container))?;
+    let previous = latest_revision(config, title_id, container)?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(io::Error::other)?;
+    // ids only go up, even when the clock goes back
+    let mut id = (now.as_millis() as u64).max(previous.as_ref().map_or(0, |previous| previous.id + 1));
+    while revision_path(config, title_id, container, id).exists() {
+        id += 1;
+    }
+
+    let path = revision_path(config, title_id, container, id);
+    fs::create_dir_all(&path)?;
+
+    let mut files = get_dir_info(format!("{}/", dir.to_string_lossy()), HashAlgorithm::MD5, index)?;
+    set_mtimes(&mut files, mtimes, previous.as_ref());
+    let blobs = store_blobs(config, index, dir, &files)?;
+
+    let revision = Revision { id, timestamp: now.as_secs(), uploader: uploader.to_string(), restored_from, files, blobs };
+    write_synced(&pending_revision_path(config, title_id, container, id), serde_json::to_string_pretty(&revision).map_err(io::Error::other)?.as_bytes())?;
+    sync_dir(&path)?;
+    sync_dir(&history_path(config, title_id, container))?;
+
+    Ok(revision)
+}
+
+// lists a prepared revision and makes it the latest, can be repeated after a crash
+pub fn publish_revision(config: &Config, title_id: u64, container: Container, revision: u64) -> io::Result<()> {
+    let pending_path = pending_revision_path(config, title_id, container, revision);
+    if pending_path.exists() {
+        let path = revision_path(config, title_id, container, revision);
+        fs::rename(pending_path, path.join("revision.json"))?;
+        sync_dir(&path)?;
+    }
+
+    write_latest(config, title_id, container, revision)
+}
+
+// revisions of commits that were rolled back, their blobs are collected on the next startup
+pub fn discard_pending_revisions(config: &Config, title_id: u64, container: Container) -> io::Result<()> {
+    let path = history_path(config, title_id, container);
+    if !path.exists() {
+        return Ok(())
+    }
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let Some(revision) = entry.file_name().to_str().and_then(|name| name.parse::<u64>().ok()) else { continue; };
+        if pending_revision_path(config, title_id, container, revision).exists() && !entry.path().join("revision.json").exists() {
+            fs::remove_dir_all(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn store_blobs(config: &Config, index: &FileIndex, dir: &Path, files: &[ServerFileInfo]) -> io::Result<BTreeMap<String, String>> {
+    let mut blobs = BTreeMap::new();
+    for file in files.iter().filter(|file| file.kind == EntryKind::FILE) {
+        blobs.insert(file.path.clone(), store_blob(config, index, &dir.join(&file.path))?);
+    }
+
+    Ok(blobs)
+}
+
+fn write_revision(path: &Path, revision: &Revision) -> io::Result<()> {
+    write_synced(&path.join("revision.json"), serde_json::to_string_pretty(revision).map_err(io::Error::other)?.as_bytes())
+}
+
+// the directory itself and the empty directories of the revision, the others are created with their files
+fn create_directories(revision: &Revision, dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for directory in revision.files.iter().filter(|file| file.kind == EntryKind::DIRECTORY) {
+        fs::create_dir_all(dir.join(&directory.path))?;
+    }
+
+    Ok(())
+}
+
+// hard links the files of a revision into a directory that is only read from, e.g. download staging
+pub fn link_revision(config: &Config, revision: &Revision, dir: &Path) -> io::Result<()> {
+    create_directories(revision, dir)?;
+    for (path, hash) in &revision.blobs {
+        link_blob(config, hash, &dir.join(path))?;
+    }
+
+    Ok(())
+}
+
+// moves the files of revisions from before the blob store into it, and points to the latest revision of older histories
+pub fn migrate_revisions(config: &Config, index: &FileIndex) -> io::Result<()> {
+    for title_id in title_ids(config) {
+        for container in [Container::SAVE, Container::EXTDATA] {
+            let revisions = list_revisions(config, title_id, container)?;
+
+            // histories from before the latest pointer
+            if let Some(latest) = revisions.last().filter(|_| !latest_path(config, title_id, container).exists()) {
+                write_latest(config, title_id, container, latest.id)?;
+            }
+
+            for mut revision in revisions {
+                let files_path = legacy_files_path(config, title_id, container, revision.id);
+                if !files_path.exists() {
+                    continue;
+                }
+
+                if revision.blobs.is_empty() {
+                    revision.blobs = store_blobs(config, index, &files_path, &revision.files)?;
+                    write_revision(&revision_path(config, title_id, container, revision.id), &revision)?;
+                }
+
+                fs::remove_dir_all(&files_path)?;
+                index.prune(&files_path);
+                println!("Moved revision {} of {:X} into the blob store", revision.id, title_id);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// copies the files of a revision into a directory that may be changed later, e.g. a restored container
+pub fn copy_revision(config: &Config, revision: &Revision, dir: &Path) -> io::Result<()> {
+    create_directories(revision, dir)?;
+    for (path, hash) in &revision.blobs {
+        copy_blob(config, hash, &dir.join(path))?;
+    }
+
+    Ok(())
+}
+
+// restoring a conflict revision picks it, so it's no longer listed as a conflict
+pub fn pick_conflict(config: &Config, title_id: u64, container: Container, revision: u64) -> io::Result<()> {
+    let mut conflicts = list_conflicts(config, title_id, container)?;
+    let count = conflicts.len();
+    conflicts.retain(|conflict| conflict.revision != revision);
+    if conflicts.len() != count {
+        write_conflicts(config, title_id, container, &conflicts)?;
+    }
+
+    Ok(())
+}
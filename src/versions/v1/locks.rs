@@ -0,0 +1,51 @@
+use std::{collections::HashMap, sync::{Arc, Mutex, PoisonError}};
+
+use rocket::tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+
+use crate::versions::v1::ticket::Container;
+
+type TitleLock = Arc<RwLock<()>>;
+
+// one reader writer lock per title container, so unrelated titles never wait on each other
+// anything that reads the live container or its history holds the read lock, commits and restores hold the write lock
+// the tickets map is only locked briefly to find or claim a ticket, never across filesystem work
+#[derive(Debug, Default)]
+pub struct TitleLocks {
+    // entries are never removed, there is only one per title container that was ever used
+    locks: Mutex<HashMap<(u64, Container), TitleLock>>
+}
+
+impl TitleLocks {
+    fn get(&self, title_id: u64, container: Container) -> TitleLock {
+        // the map is never left half changed, so a panic elsewhere doesn't make it unusable
+        let mut locks = self.locks.lock().unwrap_or_else(PoisonError::into_inner);
+        locks.entry((title_id, container)).or_default().clone()
+    }
+
+    pub async fn read(&self, title_id: u64, container: Container) -> OwnedRwLockReadGuard<()> {
+        self.get(title_id, container).read_owned().await
+    }
+
+    pub async fn write(&self, title_id: u64, container: Container) -> OwnedRwLockWriteGuard<()> {
+        self.get(title_id, container).write_owned().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn readers_share_and_writers_exclude() {
+        let locks = TitleLocks::default();
+
+        let _first = locks.read(1, Container::SAVE).await;
+        let _second = locks.read(1, Container::SAVE).await;
+        assert!(locks.get(1, Container::SAVE).try_write_owned().is_err());
+
+        // other titles and containers aren't affected
+        let _other_title = locks.write(2, Container::SAVE).await;
+        let _other_container = locks.write(1, Container::EXTDATA).await;
+        assert!(locks.get(2, Container::SAVE).try_read_owned().is_err());
+    }
+}
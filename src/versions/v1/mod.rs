@@ -1,17 +1,56 @@
-use rocket::http::Status;
+use rocket::{Request, http::Status, response::{self, Responder, Response}};
+
+use crate::versions::v1::file_info::HASH_ALGORITHMS;
 
 pub mod ticket;
+pub mod blobs;
+pub mod client_path;
+pub mod conditional;
 pub mod file_info;
+pub mod hash_index;
+pub mod locks;
+pub mod history;
 pub mod titles;
+pub mod transfer;
 pub mod upload;
 pub mod download;
+pub mod revisions;
+pub mod storage;
+pub mod pair;
+
+// 204 with the hash algorithms this server supports, in the Hash-Algorithms header
+pub struct ServerStatus;
+
+impl<'r> Responder<'r, 'static> for ServerStatus {
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'static> {
+        let algorithms: Vec<String> = HASH_ALGORITHMS.iter().map(|algorithm| algorithm.to_string()).collect();
+        Response::build().status(Status::NoContent).raw_header("Hash-Algorithms", algorithms.join(", ")).ok()
+    }
+}
+
+// 204 after which the client has the same files as the server, the revision they're at is sent in the Revision header
+// if the container has one, clients use it as the base revision of their next upload
+#[derive(Debug)]
+pub struct Synced(pub Option<u64>);
+
+impl<'r> Responder<'r, 'static> for Synced {
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.status(Status::NoContent);
+        if let Some(revision) = self.0 {
+            response.raw_header("Revision", revision.to_string());
+        }
+
+        response.ok()
+    }
+}
 
 #[get("/v1/status")]
-pub fn status_get() -> Status {
-    Status::NoContent
+pub fn status_get() -> ServerStatus {
+    ServerStatus
 }
 
 #[head("/v1/status")]
-pub fn status_head() -> Status {
-    Status::NoContent
+pub fn status_head() -> ServerStatus {
+    ServerStatus
 }
\ No newline at end of file
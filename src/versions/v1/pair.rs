@@ -0,0 +1,53 @@
+use std::net::SocketAddr;
+
+use rocket::{State, http::Status, serde::{Deserialize, json::Json}};
+use serde::Serialize;
+
+use crate::{devices::Devices, error::ApiError, pairing::{Pairing, start_pairing}};
+
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct PairBody {
+    pin: String,
+    name: String
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct PairResponse {
+    name: String,
+    token: String
+}
+
+// clients are told apart by the address they connect from, not by headers they could send themselves
+#[post("/v1/pair/begin")]
+pub fn pair_begin(pairing: &State<Pairing>, remote: SocketAddr) -> Result<Status, ApiError> {
+    if pairing.lock()?.is_locked(remote.ip()) {
+        return Err(ApiError::LockedOut)
+    }
+
+    match start_pairing(pairing) {
+        Some(_) => Ok(Status::NoContent),
+        None => Err(ApiError::Internal("Failed to start pairing".to_string()))
+    }
+}
+
+#[post("/v1/pair", format = "application/json", data = "<data>")]
+pub fn pair(pairing: &State<Pairing>, devices: &State<Devices>, remote: SocketAddr, data: Json<PairBody>) -> Result<Json<PairResponse>, ApiError> {
+    let name = data.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::BadRequest("The device needs a name".to_string()))
+    }
+
+    let mut state = pairing.lock()?;
+    state.verify(remote.ip(), &data.pin)?;
+    drop(state);
+
+    let mut store = devices.lock()?;
+    match store.add(name) {
+        Ok(Some(device)) => {
+            println!("Paired device \"{}\"", device.name);
+            Ok(Json(PairResponse { name: device.name, token: device.token }))
+        },
+        Ok(None) => Err(ApiError::NameTaken(name.to_string())),
+        Err(err) => Err(ApiError::Internal(format!("Failed to save devices: {err}")))
+    }
+}
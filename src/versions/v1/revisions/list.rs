@@ -0,0 +1,13 @@
+use std::str::FromStr;
+
+use rocket::{State, serde::json::Json};
+
+use crate::{config::Config, devices::Device, error::ApiError, versions::v1::{history::{Revision, list_revisions}, ticket::Container}};
+
+#[get("/v1/revisions/<id>/<container>")]
+pub fn revisions_list(_device: Device, config: &State<Config>, id: u64, container: &str) -> Result<Json<Vec<Revision>>, ApiError> {
+    let container = Container::from_str(container).map_err(|_| ApiError::BadRequest(format!("{container} isn't a container")))?;
+    let revisions = list_revisions(config, id, container)?;
+
+    Ok(Json(revisions))
+}
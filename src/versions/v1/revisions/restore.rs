@@ -0,0 +1,19 @@
+use std::{io::ErrorKind, str::FromStr};
+
+use rocket::{State, serde::json::Json};
+
+use crate::{devices::Device, error::ApiError, versions::v1::{history::Revision, locks::TitleLocks, storage::{Store, blocking}, ticket::Container}};
+
+#[post("/v1/revisions/<id>/<container>/<revision>/restore")]
+pub async fn revisions_restore(device: Device, locks: &State<TitleLocks>, storage: &State<Store>, id: u64, container: &str, revision: u64) -> Result<Json<Revision>, ApiError> {
+    let container = Container::from_str(container).map_err(|_| ApiError::BadRequest(format!("{container} isn't a container")))?;
+
+    // a restore is a commit like any upload
+    let _title = locks.write(id, container).await;
+    let uploader = device.name.clone();
+    match blocking(storage, move |storage| storage.restore_revision(id, container, revision, &uploader)).await {
+        Ok(restored) => Ok(Json(restored)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Err(ApiError::NotFound(format!("Revision {revision} of {:X} doesn't exist", id))),
+        Err(err) => Err(ApiError::Internal(format!("Failed to restore revision {revision} of {:X}: {err}", id)))
+    }
+}
@@ -0,0 +1,614 @@
+use std::{collections::HashMap, fs::{self, File}, io::{self, BufReader}, path::{Path, PathBuf}};
+
+use fs_extra::dir;
+use rocket::tokio::{self, io::AsyncRead};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{config::Config, versions::v1::{blobs::blob_path, client_path::{ClientPath, path_key}, file_info::{EntryKind, HashAlgorithm, ServerFileInfo, file_hash, get_dir_info}, hash_index::HashIndex, history::{ConflictRevision, Revision, add_conflict, copy_revision, discard_pending_revisions, get_revision, latest_revision, link_revision, list_conflicts, pick_conflict, prepare_revision, publish_revision, set_mtimes, title_ids}, storage::{FileReader, Storage, invalid_path, manifest_mismatch}, ticket::{Container, Ticket, TicketType, link_dir_all, link_file, ticket_partial_path, ticket_path}, transfer::{ReceivedData, receive_file}}};
+
+// the <data_directory>/<TITLEID>/<save|extdata> layout, transactions are staged in the tickets path
+//
+// commits build the new container in <TITLEID>/.<container>.new, and write <TITLEID>/.<container>.commit once it
+// is complete and synced. from then on the commit is finished even after a crash, by swapping the directories
+// with renames. without the marker anything left over is rolled back on startup, see FsStorage::recover
+pub struct FsStorage {
+    config: Config,
+    index: HashIndex
+}
+
+impl FsStorage {
+    pub fn new(config: Config, index: HashIndex) -> Self {
+        FsStorage { config, index }
+    }
+
+    fn title_path(&self, title_id: u64) -> PathBuf {
+        self.config.data_directory().join(format!("{:X}", title_id))
+    }
+
+    fn container_path(&self, title_id: u64, container: Container) -> PathBuf {
+        self.title_path(title_id).join(container.to_string().to_lowercase())
+    }
+
+    // .<container>.new, .<container>.old or .<container>.commit
+    fn swap_path(&self, title_id: u64, container: Container, suffix: &str) -> PathBuf {
+        self.title_path(title_id).join(format!(".{}.{suffix}", container.to_string().to_lowercase()))
+    }
+
+    // stores the revision of .<container>.new and writes the marker, after which the commit is only renames
+    fn prepare_commit(&self, title_id: u64, container: Container, uploader: &str, restored_from: Option<u64>, mtimes: &HashMap<String, u64>) -> io::Result<CommitMarker> {
+        let revision = prepare_revision(&self.config, &self.index, title_id, container, &self.swap_path(title_id, container, "new"), uploader, restored_from, mtimes)?;
+
+        let marker = CommitMarker { uploader: uploader.to_string(), restored_from, revision: Some(revision.id), mtimes: mtimes.clone() };
+        self.write_marker(title_id, container, &marker)?;
+
+        Ok(marker)
+    }
+
+    // from here on the commit can't be rolled back anymore
+    fn write_marker(&self, title_id: u64, container: Container, marker: &CommitMarker) -> io::Result<()> {
+        sync_dir_all(&self.swap_path(title_id, container, "new"))?;
+        write_synced(&self.swap_path(title_id, container, "commit"), serde_json::to_string(marker).map_err(io::Error::other)?.as_bytes())?;
+        sync_dir(&self.title_path(title_id))
+    }
+
+    // swaps in the new container and publishes its revision, every step can be repeated after a crash
+    fn finish_commit(&self, title_id: u64, container: Container, marker: &CommitMarker) -> io::Result<Revision> {
+        let container_path = self.container_path(title_id, container);
+        let new_path = self.swap_path(title_id, container, "new");
+        let old_path = self.swap_path(title_id, container, "old");
+
+        if new_path.exists() {
+            if container_path.exists() {
+                remove_path(&old_path)?;
+                fs::rename(&container_path, &old_path)?;
+            }
+
+            fs::rename(&new_path, &container_path)?;
+            sync_dir(&self.title_path(title_id))?;
+        }
+
+        remove_path(&old_path)?;
+
+        // markers from before revisions were prepared with their commit, the revision is taken from the swapped in container
+        let revision = match marker.revision {
+            Some(revision) => revision,
+            None => prepare_revision(&self.config, &self.index, title_id, container, &container_path, &marker.uploader, marker.restored_from, &marker.mtimes)?.id
+        };
+
+        publish_revision(&self.config, title_id, container, revision)?;
+        if let Some(restored) = marker.restored_from {
+            pick_conflict(&self.config, title_id, container, restored)?;
+        }
+
+        fs::remove_file(self.swap_path(title_id, container, "commit"))?;
+
+        // the files were hashed in .<container>.new, and are the same files in the container
+        self.index.rename_dir(&new_path, &container_path);
+        self.save_index();
+
+        get_revision(&self.config, title_id, container, revision)
+    }
+
+    // finishes a commit that got past its marker, and rolls back anything else a commit left behind
+    fn recover_container(&self, title_id: u64, container: Container) -> io::Result<Recovery> {
+        let container_path = self.container_path(title_id, container);
+        let new_path = self.swap_path(title_id, container, "new");
+        let old_path = self.swap_path(title_id, container, "old");
+
+        let marker_path = self.swap_path(title_id, container, "commit");
+        if marker_path.exists() {
+            let marker: CommitMarker = serde_json::from_reader(BufReader::new(File::open(&marker_path)?)).map_err(io::Error::other)?;
+            self.finish_commit(title_id, container, &marker)?;
+
+            return Ok(Recovery::Finished)
+        }
+
+        let recovery = match new_path.exists() || old_path.exists() {
+            true => Recovery::RolledBack,
+            false => Recovery::Clean
+        };
+
+        remove_path(&new_path)?;
+        if old_path.exists() && !container_path.exists() {
+            fs::rename(&old_path, &container_path)?;
+        }
+
+        remove_path(&old_path)?;
+        discard_pending_revisions(&self.config, title_id, container)?;
+
+        Ok(recovery)
+    }
+
+    // a commit that failed before its marker was written leaves nothing behind
+    fn roll_back(&self, title_id: u64, container: Container) {
+        if self.recover_container(title_id, container).is_err() {
+            println!("Failed to roll back the commit of {:X} ({container})", title_id);
+        }
+    }
+
+    // finishes commits that got past their commit marker before the server stopped, and rolls back the rest
+    pub fn recover(&self) -> io::Result<()> {
+        for title_id in title_ids(&self.config) {
+            for container in [Container::SAVE, Container::EXTDATA] {
+                match self.recover_container(title_id, container)? {
+                    Recovery::Finished   => println!("Finished interrupted commit of {:X} ({container})", title_id),
+                    Recovery::RolledBack => println!("Rolled back interrupted commit of {:X} ({container})", title_id),
+                    Recovery::Clean      => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // links the files of the upload into .<container>.new
+    fn build_commit(&self, ticket: &Ticket) -> io::Result<()> {
+        let container_path = self.container_path(ticket.title_id, ticket.container);
+        let new_path = self.swap_path(ticket.title_id, ticket.container, "new");
+
+        // staging is in the data directory, so this only links files and never copies them
+        let staging_path = ticket_path(&self.config, ticket.id);
+        let Some(manifest) = &ticket.manifest else {
+            if container_path.exists() {
+                link_dir_all(&container_path, &new_path)?;
+            }
+
+            return link_dir_all(&staging_path, &new_path)
+        };
+
+        // files the client didn't send are unchanged, anything that isn't in the manifest is left out
+        // unchanged files may be in another case on the server, they're renamed to the manifests spelling
+        fs::create_dir_all(&new_path)?;
+        let existing = files_by_key(&container_path)?;
+        for file in manifest {
+            // tickets from before paths were validated may still have any path
+            let path = ClientPath::parse(&file.path).map_err(|err| invalid_path(&file.path, err))?;
+            if file.kind == EntryKind::DIRECTORY {
+                fs::create_dir_all(path.join_to(&new_path))?;
+                continue;
+            }
+
+            let staged = path.join_to(&staging_path);
+            let source = match staged.is_file() {
+                true => staged,
+                false => {
+                    let Some(source) = existing.get(&path.key()).filter(|source| source.is_file()) else {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} was neither uploaded nor on the server", file.path)))
+                    };
+
+                    // the server may have changed since the client compared its files
+                    let size = fs::metadata(source)?.len();
+                    let hash = self.index.hash(source, ticket.algorithm)?;
+                    if !ticket.matches_manifest(&file.path, size, &hash) {
+                        return Err(manifest_mismatch(&file.path))
+                    }
+
+                    source.clone()
+                }
+            };
+
+            link_file(source, path.join_to(&new_path))?;
+        }
+
+        // the received files were hashed while uploading, so they don't need to be hashed again
+        for file in &ticket.received_files {
+            let Ok(path) = ClientPath::parse(&file.path) else { continue; };
+            self.index.insert(&path.join_to(&new_path), ticket.algorithm, file.hash.clone());
+        }
+
+        Ok(())
+    }
+
+    fn staging_file(&self, ticket: Uuid, path: &ClientPath) -> PathBuf {
+        path.join_to(&ticket_path(&self.config, ticket))
+    }
+
+    fn partial_file(&self, ticket: Uuid, path: &ClientPath) -> PathBuf {
+        path.join_to(&ticket_partial_path(&self.config, ticket))
+    }
+
+    fn save_index(&self) {
+        if self.index.save(&self.config).is_err() {
+            println!("Failed to save the file index");
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for FsStorage {
+    fn list_titles(&self) -> io::Result<Vec<u64>> {
+        let mut titles = title_ids(&self.config);
+        titles.sort();
+
+        Ok(titles)
+    }
+
+    fn read_manifest(&self, title_id: u64, container: Container, revision: Option<u64>, algorithm: HashAlgorithm) -> io::Result<Option<Vec<ServerFileInfo>>> {
+        let files = match revision {
+            Some(revision) => {
+                let revision = match get_revision(&self.config, title_id, container, revision) {
+                    Ok(revision) => revision,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+                    Err(err) => return Err(err)
+                };
+
+                // blobs never change, so their hashes can be cached like any other file
+                let mut files = Vec::new();
+                for file in revision.files {
+                    if file.kind == EntryKind::DIRECTORY {
+                        files.push(file);
+                        continue;
+                    }
+
+                    let Some(blob) = revision.blobs.get(&file.path) else { continue; };
+                    let hash = self.index.hash(&blob_path(&self.config, blob), algorithm)?;
+                    files.push(ServerFileInfo { hash, ..file });
+                }
+
+                files
+            },
+            None => {
+                let container_path = self.container_path(title_id, container);
+                if !container_path.exists() {
+                    return Ok(None)
+                }
+
+                // the live files are links to staged uploads, their mtimes are the ones recorded with the newest revision
+                let mut files = get_dir_info(format!("{}/", container_path.to_string_lossy()), algorithm, &self.index)?;
+                if let Some(latest) = latest_revision(&self.config, title_id, container)? {
+                    set_mtimes(&mut files, &latest.mtimes(), None);
+                }
+
+                files
+            }
+        };
+
+        self.save_index();
+        Ok(Some(files))
+    }
+
+    fn latest_revision(&self, title_id: u64, container: Container) -> io::Result<Option<Revision>> {
+        latest_revision(&self.config, title_id, container)
+    }
+
+    fn list_conflicts(&self, title_id: u64, container: Container) -> io::Result<Vec<ConflictRevision>> {
+        list_conflicts(&self.config, title_id, container)
+    }
+
+    fn add_conflict(&self, title_id: u64, container: Container, conflict: ConflictRevision) -> io::Result<()> {
+        add_conflict(&self.config, title_id, container, conflict)
+    }
+
+    // built and swapped in like an upload, the copies are new files with the mtimes of the restored revision
+    fn restore_revision(&self, title_id: u64, container: Container, revision: u64, uploader: &str) -> io::Result<Revision> {
+        let restored = get_revision(&self.config, title_id, container, revision)?;
+        self.recover_container(title_id, container)?;
+
+        let prepared = copy_revision(&self.config, &restored, &self.swap_path(title_id, container, "new"))
+            .and_then(|_| self.prepare_commit(title_id, container, uploader, Some(revision), &restored.mtimes()));
+
+        match prepared {
+            Ok(marker) => self.finish_commit(title_id, container, &marker),
+            Err(err) => {
+                self.roll_back(title_id, container);
+                Err(err)
+            }
+        }
+    }
+
+    fn begin_transaction(&self, ticket: &Ticket) -> io::Result<()> {
+        let staging_path = ticket_path(&self.config, ticket.id);
+        if ticket.kind == TicketType::UPLOAD {
+            return fs::create_dir_all(staging_path)
+        }
+
+        match ticket.revision {
+            Some(revision) => link_revision(&self.config, &get_revision(&self.config, ticket.title_id, ticket.container, revision)?, &staging_path),
+            None => {
+                let container_path = self.container_path(ticket.title_id, ticket.container);
+                if !container_path.exists() {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "The container doesn't exist"))
+                }
+
+                link_dir_all(container_path, staging_path)
+            }
+        }
+    }
+
+    fn commit_transaction(&self, ticket: &Ticket, uploader: &str) -> io::Result<Revision> {
+        let (title_id, container) = (ticket.title_id, ticket.container);
+        self.recover_container(title_id, container)?;
+
+        let prepared = self.build_commit(ticket).and_then(|_| self.prepare_commit(title_id, container, uploader, None, &ticket.mtimes()));
+        let committed = match prepared {
+            Ok(marker) => self.finish_commit(title_id, container, &marker),
+            Err(err) => {
+                self.roll_back(title_id, container);
+                Err(err)
+            }
+        };
+
+        if self.abort_transaction(ticket.id).is_err() {
+            println!("Failed to clear ticket path {}", ticket.id.hyphenated());
+        }
+
+        committed
+    }
+
+    fn abort_transaction(&self, ticket: Uuid) -> io::Result<()> {
+        dir::remove(ticket_partial_path(&self.config, ticket)).map_err(io::Error::other)?;
+        dir::remove(ticket_path(&self.config, ticket)).map_err(io::Error::other)
+    }
+
+    async fn write_file(&self, ticket: Uuid, path: &ClientPath, offset: u64, body: &mut (dyn AsyncRead + Send + Unpin), limit: u64, algorithm: HashAlgorithm) -> io::Result<ReceivedData> {
+        receive_file(body, limit, &self.partial_file(ticket, path), offset, algorithm).await
+    }
+
+    fn hash_partial_file(&self, ticket: Uuid, path: &ClientPath, algorithm: HashAlgorithm) -> io::Result<String> {
+        file_hash(&self.partial_file(ticket, path), algorithm)
+    }
+
+    fn finish_file(&self, ticket: Uuid, path: &ClientPath) -> io::Result<bool> {
+        let file_path = self.staging_file(ticket, path);
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let created = !file_path.exists();
+        fs::rename(self.partial_file(ticket, path), &file_path)?;
+
+        Ok(created)
+    }
+
+    fn discard_file(&self, ticket: Uuid, path: &ClientPath) -> io::Result<()> {
+        match fs::remove_file(self.partial_file(ticket, path)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(())
+        }
+    }
+
+    fn hash_file(&self, ticket: Uuid, path: &ClientPath, algorithm: HashAlgorithm) -> io::Result<String> {
+        file_hash(&self.staging_file(ticket, path), algorithm)
+    }
+
+    // streamed from disk instead of being read into memory
+    async fn open_file(&self, ticket: Uuid, path: &ClientPath) -> io::Result<(Box<dyn FileReader>, u64)> {
+        let file_path = self.staging_file(ticket, path);
+        let metadata = tokio::fs::metadata(&file_path).await?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Not a file"))
+        }
+
+        Ok((Box::new(tokio::fs::File::open(&file_path).await?), metadata.len()))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct CommitMarker {
+    uploader: String,
+    // restores record the revision they restored
+    #[serde(default)]
+    restored_from: Option<u64>,
+    // the prepared revision that is published once the container is swapped in
+    // None in markers from before revisions were prepared with their commit
+    #[serde(default)]
+    revision: Option<u64>,
+    // kept with the marker so an interrupted commit still records them
+    #[serde(default)]
+    mtimes: HashMap<String, u64>
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Recovery {
+    Clean,
+    Finished,
+    RolledBack
+}
+
+// every file under dir by its path key
+fn files_by_key(dir: &Path) -> io::Result<HashMap<String, PathBuf>> {
+    let mut files = HashMap::new();
+    if !dir.exists() {
+        return Ok(files)
+    }
+
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        for entry in fs::read_dir(current)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                dirs.push(entry.path());
+                continue;
+            }
+
+            let Some(relative) = entry.path().strip_prefix(dir).ok().and_then(Path::to_str).map(str::to_string) else { continue; };
+            files.insert(path_key(&relative), entry.path());
+        }
+    }
+
+    Ok(files)
+}
+
+fn remove_path(path: &Path) -> io::Result<()> {
+    match path.is_dir() {
+        true => fs::remove_dir_all(path),
+        false => match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(())
+        }
+    }
+}
+
+// directories can't be opened for syncing on windows, where renames are flushed with the directory anyway
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(path)?.sync_all()?;
+
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+fn sync_dir_all(path: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        match entry.file_type()?.is_dir() {
+            true => sync_dir_all(&entry.path())?,
+            false => sync_file(&entry.path())?
+        }
+    }
+
+    sync_dir(path)
+}
+
+// windows only flushes files that are open for writing
+pub fn sync_file(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    return File::open(path)?.sync_all();
+
+    #[cfg(not(unix))]
+    return fs::OpenOptions::new().write(true).open(path)?.sync_all();
+}
+
+pub fn write_synced(path: &Path, data: &[u8]) -> io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, data)?;
+    sync_file(&temp_path)?;
+
+    fs::rename(temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::versions::v1::storage::tests::{TITLE, fs_storage, upload};
+    use crate::versions::v1::history::history_path;
+
+    async fn commit(storage: &FsStorage, name: &str, data: &[u8]) {
+        let ticket = upload(storage, &[(name, data)]).await;
+        storage.commit_transaction(&ticket, "test").unwrap();
+    }
+
+    fn swap_files(storage: &FsStorage) -> Vec<String> {
+        fs::read_dir(storage.title_path(TITLE)).unwrap().flatten().map(|entry| entry.file_name().to_string_lossy().to_string()).filter(|name| name.starts_with('.')).collect()
+    }
+
+    #[rocket::async_test]
+    async fn commit_leaves_nothing_behind() {
+        let (_dir, storage) = fs_storage();
+        commit(&storage, "/a", b"first").await;
+        commit(&storage, "/a", b"second").await;
+
+        assert!(swap_files(&storage).is_empty());
+        assert_eq!(fs::read(storage.container_path(TITLE, Container::SAVE).join("a")).unwrap(), b"second");
+    }
+
+    #[rocket::async_test]
+    async fn unmarked_commit_is_rolled_back() {
+        let (_dir, storage) = fs_storage();
+        commit(&storage, "/a", b"first").await;
+        let latest = storage.latest_revision(TITLE, Container::SAVE).unwrap();
+
+        // interrupted after the container was moved away, but before the marker was written
+        let container_path = storage.container_path(TITLE, Container::SAVE);
+        let new_path = storage.swap_path(TITLE, Container::SAVE, "new");
+        fs::create_dir_all(&new_path).unwrap();
+        fs::write(new_path.join("a"), b"second").unwrap();
+        let pending = prepare_revision(&storage.config, &storage.index, TITLE, Container::SAVE, &new_path, "interrupted", None, &HashMap::new()).unwrap();
+        fs::rename(&container_path, storage.swap_path(TITLE, Container::SAVE, "old")).unwrap();
+
+        storage.recover().unwrap();
+        assert!(swap_files(&storage).is_empty());
+        assert_eq!(fs::read(container_path.join("a")).unwrap(), b"first");
+        assert_eq!(storage.latest_revision(TITLE, Container::SAVE).unwrap(), latest);
+        assert!(!history_path(&storage.config, TITLE, Container::SAVE).join(pending.id.to_string()).exists());
+    }
+
+    #[rocket::async_test]
+    async fn marked_commit_is_finished() {
+        let (_dir, storage) = fs_storage();
+        commit(&storage, "/a", b"first").await;
+
+        // interrupted between the two renames, with the marker already written
+        let container_path = storage.container_path(TITLE, Container::SAVE);
+        let new_path = storage.swap_path(TITLE, Container::SAVE, "new");
+        fs::create_dir_all(&new_path).unwrap();
+        fs::write(new_path.join("a"), b"second").unwrap();
+
+        storage.prepare_commit(TITLE, Container::SAVE, "interrupted", None, &HashMap::from([("a".to_string(), 100)])).unwrap();
+        fs::rename(&container_path, storage.swap_path(TITLE, Container::SAVE, "old")).unwrap();
+
+        storage.recover().unwrap();
+        assert!(swap_files(&storage).is_empty());
+        assert_eq!(fs::read(container_path.join("a")).unwrap(), b"second");
+
+        let latest = storage.latest_revision(TITLE, Container::SAVE).unwrap().unwrap();
+        assert_eq!((latest.uploader.as_str(), latest.files[0].mtime), ("interrupted", Some(100)));
+    }
+
+    #[rocket::async_test]
+    async fn marker_without_revision_is_finished() {
+        let (_dir, storage) = fs_storage();
+        commit(&storage, "/a", b"first").await;
+
+        // markers from before revisions were prepared with their commit
+        let new_path = storage.swap_path(TITLE, Container::SAVE, "new");
+        fs::create_dir_all(&new_path).unwrap();
+        fs::write(new_path.join("a"), b"second").unwrap();
+
+        let marker = CommitMarker { uploader: "interrupted".to_string(), restored_from: None, revision: None, mtimes: HashMap::new() };
+        storage.write_marker(TITLE, Container::SAVE, &marker).unwrap();
+
+        storage.recover().unwrap();
+        assert!(swap_files(&storage).is_empty());
+        assert_eq!(fs::read(storage.container_path(TITLE, Container::SAVE).join("a")).unwrap(), b"second");
+        assert_eq!(storage.latest_revision(TITLE, Container::SAVE).unwrap().unwrap().uploader, "interrupted");
+    }
+
+    #[rocket::async_test]
+    async fn revision_ids_only_go_up() {
+        let (_dir, storage) = fs_storage();
+        commit(&storage, "/a", b"first").await;
+
+        // a revision from before the clock went back a day
+        let mut future = storage.latest_revision(TITLE, Container::SAVE).unwrap().unwrap();
+        future.id += 24 * 60 * 60 * 1000;
+        let future_path = history_path(&storage.config, TITLE, Container::SAVE).join(future.id.to_string());
+        fs::create_dir_all(&future_path).unwrap();
+        fs::write(future_path.join("revision.pending.json"), serde_json::to_string(&future).unwrap()).unwrap();
+        publish_revision(&storage.config, TITLE, Container::SAVE, future.id).unwrap();
+
+        commit(&storage, "/a", b"second").await;
+        assert_eq!(storage.latest_revision(TITLE, Container::SAVE).unwrap().unwrap().id, future.id + 1);
+    }
+
+    #[rocket::async_test]
+    async fn latest_revision_skips_the_history() {
+        let (_dir, storage) = fs_storage();
+        commit(&storage, "/a", b"first").await;
+        let first = storage.latest_revision(TITLE, Container::SAVE).unwrap().unwrap();
+        commit(&storage, "/a", b"second").await;
+
+        // older revisions aren't read at all
+        fs::write(history_path(&storage.config, TITLE, Container::SAVE).join(first.id.to_string()).join("revision.json"), b"{").unwrap();
+        assert_ne!(storage.latest_revision(TITLE, Container::SAVE).unwrap().unwrap().id, first.id);
+        assert!(storage.read_manifest(TITLE, Container::SAVE, None, HashAlgorithm::MD5).unwrap().is_some());
+    }
+
+    #[rocket::async_test]
+    async fn restore_is_committed_like_an_upload() {
+        let (_dir, storage) = fs_storage();
+        commit(&storage, "/a", b"first").await;
+        let first = storage.latest_revision(TITLE, Container::SAVE).unwrap().unwrap();
+        commit(&storage, "/b", b"second").await;
+
+        let restored = storage.restore_revision(TITLE, Container::SAVE, first.id, "restorer").unwrap();
+        assert_eq!((restored.restored_from, restored.files.clone()), (Some(first.id), first.files));
+        assert!(swap_files(&storage).is_empty());
+        assert_eq!(files_by_key(&storage.container_path(TITLE, Container::SAVE)).unwrap().into_keys().collect::<Vec<_>>(), vec!["a".to_string()]);
+
+        assert_eq!(storage.restore_revision(TITLE, Container::SAVE, 1, "restorer").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,235 @@
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, io::{self, Cursor}, sync::{Mutex, MutexGuard}};
+
+use rocket::tokio::io::{AsyncRead, AsyncReadExt};
+use uuid::Uuid;
+
+use crate::versions::v1::{client_path::{ClientPath, path_key}, file_info::{EntryKind, HashAlgorithm, Hasher, ServerFileInfo}, history::{ConflictRevision, Revision, set_mtimes}, storage::{FileReader, Storage, invalid_path, manifest_mismatch}, ticket::{Container, Ticket, TicketType, unix_time}, transfer::ReceivedData};
+
+type Files = BTreeMap<String, Vec<u8>>;
+type Folders = BTreeSet<String>;
+
+#[derive(Debug, Default)]
+struct Transaction {
+    files: Files,
+    partial: HashMap<String, Vec<u8>>
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    containers: HashMap<(u64, Container), Files>,
+    folders: HashMap<(u64, Container), Folders>,
+    revisions: HashMap<(u64, Container), Revision>,
+    conflicts: HashMap<(u64, Container), Vec<ConflictRevision>>,
+    transactions: HashMap<Uuid, Transaction>
+}
+
+// keeps everything in memory and only remembers the newest revision, so old revisions never exist
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    state: Mutex<MemoryState>
+}
+
+fn key(path: &ClientPath) -> String {
+    path.relative()
+}
+
+fn hash(data: &[u8], algorithm: HashAlgorithm) -> String {
+    let mut hasher = Hasher::new(algorithm);
+    hasher.update(data);
+
+    hasher.finalize()
+}
+
+// like on disk only empty folders are listed, the others are implied by their contents
+fn manifest(files: &Files, folders: Option<&Folders>, algorithm: HashAlgorithm) -> Vec<ServerFileInfo> {
+    let mut manifest: Vec<ServerFileInfo> = files.iter().map(|(path, data)| ServerFileInfo { path: path.clone(), size: data.len() as u64, hash: hash(data, algorithm), kind: EntryKind::FILE, mtime: None }).collect();
+    for folder in folders.into_iter().flatten() {
+        let prefix = format!("{folder}/");
+        if !files.keys().chain(folders.into_iter().flatten()).any(|path| path.starts_with(&prefix)) {
+            manifest.push(ServerFileInfo::directory(folder.clone()));
+        }
+    }
+
+    manifest.sort_by(|a, b| a.path.cmp(&b.path));
+    manifest
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "Not found")
+}
+
+impl MemoryStorage {
+    fn state(&self) -> io::Result<MutexGuard<'_, MemoryState>> {
+        self.state.lock().map_err(|_| io::Error::other("Memory storage lock is poisoned"))
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for MemoryStorage {
+    fn list_titles(&self) -> io::Result<Vec<u64>> {
+        let mut titles: Vec<u64> = self.state()?.containers.keys().map(|(title_id, _)| *title_id).collect();
+        titles.sort();
+        titles.dedup();
+
+        Ok(titles)
+    }
+
+    fn read_manifest(&self, title_id: u64, container: Container, revision: Option<u64>, algorithm: HashAlgorithm) -> io::Result<Option<Vec<ServerFileInfo>>> {
+        if revision.is_some() {
+            return Ok(None)
+        }
+
+        let state = self.state()?;
+        let Some(files) = state.containers.get(&(title_id, container)) else { return Ok(None) };
+
+        let mut files = manifest(files, state.folders.get(&(title_id, container)), algorithm);
+        if let Some(revision) = state.revisions.get(&(title_id, container)) {
+            set_mtimes(&mut files, &revision.mtimes(), None);
+        }
+
+        Ok(Some(files))
+    }
+
+    fn latest_revision(&self, title_id: u64, container: Container) -> io::Result<Option<Revision>> {
+        Ok(self.state()?.revisions.get(&(title_id, container)).cloned())
+    }
+
+    fn list_conflicts(&self, title_id: u64, container: Container) -> io::Result<Vec<ConflictRevision>> {
+        Ok(self.state()?.conflicts.get(&(title_id, container)).cloned().unwrap_or_default())
+    }
+
+    fn add_conflict(&self, title_id: u64, container: Container, conflict: ConflictRevision) -> io::Result<()> {
+        self.state()?.conflicts.entry((title_id, container)).or_default().push(conflict);
+        Ok(())
+    }
+
+    // only the newest revision is kept, so it's the only one that can be restored
+    fn restore_revision(&self, title_id: u64, container: Container, revision: u64, uploader: &str) -> io::Result<Revision> {
+        let mut state = self.state()?;
+        let latest = state.revisions.get(&(title_id, container)).filter(|latest| latest.id == revision).cloned().ok_or_else(not_found)?;
+
+        let restored = Revision { id: latest.id + 1, timestamp: unix_time(), uploader: uploader.to_string(), restored_from: Some(revision), ..latest };
+        state.revisions.insert((title_id, container), restored.clone());
+        if let Some(conflicts) = state.conflicts.get_mut(&(title_id, container)) {
+            conflicts.retain(|conflict| conflict.revision != revision);
+        }
+
+        Ok(restored)
+    }
+
+    fn begin_transaction(&self, ticket: &Ticket) -> io::Result<()> {
+        let mut state = self.state()?;
+        let files = match ticket.kind {
+            TicketType::UPLOAD => Files::new(),
+            TicketType::DOWNLOAD if ticket.revision.is_some() => return Err(not_found()),
+            TicketType::DOWNLOAD => state.containers.get(&(ticket.title_id, ticket.container)).cloned().ok_or_else(not_found)?
+        };
+
+        state.transactions.insert(ticket.id, Transaction { files, partial: HashMap::new() });
+        Ok(())
+    }
+
+    fn commit_transaction(&self, ticket: &Ticket, uploader: &str) -> io::Result<Revision> {
+        let mut guard = self.state()?;
+        let state = &mut *guard;
+        let transaction = state.transactions.remove(&ticket.id).ok_or_else(not_found)?;
+        let files = state.containers.entry((ticket.title_id, ticket.container)).or_default();
+        let folders = state.folders.entry((ticket.title_id, ticket.container)).or_default();
+
+        if let Some(manifest) = &ticket.manifest {
+            let mut mirrored = Files::new();
+            folders.clear();
+            for file in manifest {
+                let path = ClientPath::parse(&file.path).map_err(|err| invalid_path(&file.path, err))?;
+                if file.kind == EntryKind::DIRECTORY {
+                    folders.insert(path.relative());
+                    continue;
+                }
+
+                let existing = files.iter().find(|(name, _)| path_key(name) == path.key()).map(|(_, data)| data);
+                let path = path.relative();
+                let data = match (transaction.files.get(&path), existing) {
+                    (Some(data), _) => data,
+                    (None, Some(data)) if ticket.matches_manifest(&file.path, data.len() as u64, &hash(data, ticket.algorithm)) => data,
+                    (None, Some(_)) => return Err(manifest_mismatch(&file.path)),
+                    (None, None) => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} was neither uploaded nor on the server", file.path)))
+                };
+
+                mirrored.insert(path, data.clone());
+            }
+
+            *files = mirrored;
+        } else {
+            files.extend(transaction.files);
+        }
+
+        let mut files = manifest(files, Some(folders), HashAlgorithm::MD5);
+        let previous = state.revisions.get(&(ticket.title_id, ticket.container));
+        set_mtimes(&mut files, &ticket.mtimes(), previous);
+
+        let id = previous.map(|revision| revision.id + 1).unwrap_or(1);
+        let revision = Revision { id, timestamp: unix_time(), uploader: uploader.to_string(), restored_from: None, files, blobs: BTreeMap::new() };
+        state.revisions.insert((ticket.title_id, ticket.container), revision.clone());
+
+        Ok(revision)
+    }
+
+    fn abort_transaction(&self, ticket: Uuid) -> io::Result<()> {
+        self.state()?.transactions.remove(&ticket);
+        Ok(())
+    }
+
+    async fn write_file(&self, ticket: Uuid, path: &ClientPath, offset: u64, body: &mut (dyn AsyncRead + Send + Unpin), limit: u64, algorithm: HashAlgorithm) -> io::Result<ReceivedData> {
+        let mut data = Vec::new();
+        body.take(limit + 1).read_to_end(&mut data).await?;
+
+        let mut state = self.state()?;
+        let transaction = state.transactions.get_mut(&ticket).ok_or_else(not_found)?;
+
+        let partial = transaction.partial.entry(key(path)).or_default();
+        partial.resize(offset as usize, 0);
+        partial.extend_from_slice(&data);
+
+        Ok(ReceivedData { size: data.len() as u64, hash: hash(&data, algorithm), complete: data.len() as u64 <= limit })
+    }
+
+    fn hash_partial_file(&self, ticket: Uuid, path: &ClientPath, algorithm: HashAlgorithm) -> io::Result<String> {
+        let state = self.state()?;
+        let transaction = state.transactions.get(&ticket).ok_or_else(not_found)?;
+
+        Ok(hash(transaction.partial.get(&key(path)).ok_or_else(not_found)?, algorithm))
+    }
+
+    fn finish_file(&self, ticket: Uuid, path: &ClientPath) -> io::Result<bool> {
+        let mut state = self.state()?;
+        let transaction = state.transactions.get_mut(&ticket).ok_or_else(not_found)?;
+
+        let data = transaction.partial.remove(&key(path)).ok_or_else(not_found)?;
+        Ok(transaction.files.insert(key(path), data).is_none())
+    }
+
+    fn discard_file(&self, ticket: Uuid, path: &ClientPath) -> io::Result<()> {
+        if let Some(transaction) = self.state()?.transactions.get_mut(&ticket) {
+            transaction.partial.remove(&key(path));
+        }
+
+        Ok(())
+    }
+
+    fn hash_file(&self, ticket: Uuid, path: &ClientPath, algorithm: HashAlgorithm) -> io::Result<String> {
+        let state = self.state()?;
+        let transaction = state.transactions.get(&ticket).ok_or_else(not_found)?;
+
+        Ok(hash(transaction.files.get(&key(path)).ok_or_else(not_found)?, algorithm))
+    }
+
+    async fn open_file(&self, ticket: Uuid, path: &ClientPath) -> io::Result<(Box<dyn FileReader>, u64)> {
+        let state = self.state()?;
+        let transaction = state.transactions.get(&ticket).ok_or_else(not_found)?;
+
+        let data = transaction.files.get(&key(path)).ok_or_else(not_found)?.clone();
+        let size = data.len() as u64;
+
+        Ok((Box::new(Cursor::new(data)), size))
+    }
+}
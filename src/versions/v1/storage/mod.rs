@@ -0,0 +1,83 @@
+use std::{error::Error, fmt, io, sync::Arc};
+
+use rocket::tokio::{self, io::{AsyncRead, AsyncSeek}};
+use uuid::Uuid;
+
+use crate::versions::v1::{client_path::{ClientPath, PathError}, file_info::{HashAlgorithm, ServerFileInfo}, history::{ConflictRevision, Revision}, ticket::{Container, Ticket}, transfer::ReceivedData};
+
+pub mod filesystem;
+#[cfg(test)]
+pub mod memory;
+#[cfg(test)]
+mod tests;
+
+pub trait FileReader: AsyncRead + AsyncSeek + Send + Unpin {}
+impl<T: AsyncRead + AsyncSeek + Send + Unpin> FileReader for T {}
+
+// where titles are kept, handlers never touch the data directory themselves
+// uploads and downloads are transactions named by the id of their ticket, paths are relative to the container
+// and already validated, see client_path.rs
+#[rocket::async_trait]
+pub trait Storage: Send + Sync {
+    fn list_titles(&self) -> io::Result<Vec<u64>>;
+    // None if the container or revision doesn't exist, paths have no leading slash
+    fn read_manifest(&self, title_id: u64, container: Container, revision: Option<u64>, algorithm: HashAlgorithm) -> io::Result<Option<Vec<ServerFileInfo>>>;
+    // the revision the live container mirrors, None if nothing was ever committed to it
+    fn latest_revision(&self, title_id: u64, container: Container) -> io::Result<Option<Revision>>;
+    // revisions that were replaced by forced uploads and haven't been picked yet
+    fn list_conflicts(&self, title_id: u64, container: Container) -> io::Result<Vec<ConflictRevision>>;
+    fn add_conflict(&self, title_id: u64, container: Container, conflict: ConflictRevision) -> io::Result<()>;
+    // replaces the container with an older revision, recorded as a new revision so history stays append only
+    // NotFound if the revision doesn't exist
+    fn restore_revision(&self, title_id: u64, container: Container, revision: u64, uploader: &str) -> io::Result<Revision>;
+
+    // uploads start empty, downloads start as a snapshot of the container or revision of the ticket
+    fn begin_transaction(&self, ticket: &Ticket) -> io::Result<()>;
+    // writes the files of an upload into its container, and records them as a new revision which is returned
+    fn commit_transaction(&self, ticket: &Ticket, uploader: &str) -> io::Result<Revision>;
+    fn abort_transaction(&self, ticket: Uuid) -> io::Result<()>;
+
+    // writes body into the unfinished copy of path starting at offset, anything after offset is discarded first
+    // reads at most one byte past limit, if it got that far the data isn't complete
+    async fn write_file(&self, ticket: Uuid, path: &ClientPath, offset: u64, body: &mut (dyn AsyncRead + Send + Unpin), limit: u64, algorithm: HashAlgorithm) -> io::Result<ReceivedData>;
+    fn hash_partial_file(&self, ticket: Uuid, path: &ClientPath, algorithm: HashAlgorithm) -> io::Result<String>;
+    // moves the unfinished copy into the transaction, returns true if the file didn't exist yet
+    fn finish_file(&self, ticket: Uuid, path: &ClientPath) -> io::Result<bool>;
+    fn discard_file(&self, ticket: Uuid, path: &ClientPath) -> io::Result<()>;
+
+    fn hash_file(&self, ticket: Uuid, path: &ClientPath, algorithm: HashAlgorithm) -> io::Result<String>;
+    // the file and its size
+    async fn open_file(&self, ticket: Uuid, path: &ClientPath) -> io::Result<(Box<dyn FileReader>, u64)>;
+}
+
+pub type Store = Arc<dyn Storage>;
+
+// runs work that reads or hashes whole files on the blocking thread pool, so it doesn't stall other requests
+pub async fn blocking<T, F>(storage: &Store, work: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn Storage) -> io::Result<T> + Send + 'static
+{
+    let storage = storage.clone();
+    tokio::task::spawn_blocking(move || work(storage.as_ref())).await.map_err(io::Error::other)?
+}
+
+pub fn invalid_path(path: &str, err: PathError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{path} isn't a valid path: {err}"))
+}
+
+// a file a commit would keep from the server that isn't what the manifest declared, e.g. because it changed since the upload began
+#[derive(Debug)]
+pub struct ManifestMismatch(pub String);
+
+impl fmt::Display for ManifestMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} on the server doesn't match the manifest", self.0)
+    }
+}
+
+impl Error for ManifestMismatch {}
+
+pub fn manifest_mismatch(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, ManifestMismatch(path.to_string()))
+}
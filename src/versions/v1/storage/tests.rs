@@ -0,0 +1,291 @@
+// every storage has to behave the same, so each test runs against MemoryStorage and against FsStorage in a temporary directory
+use std::{io::{self, Cursor}, sync::Arc};
+
+use rocket::tokio::io::AsyncReadExt;
+use tempfile::TempDir;
+
+use crate::{config::Config, versions::v1::{client_path::ClientPath, file_info::{ClientFileInfo, EntryKind, HashAlgorithm, Hasher, ServerFileInfo}, hash_index::FileIndex, history::ConflictRevision, storage::{Storage, filesystem::FsStorage, memory::MemoryStorage}, ticket::{Container, Ticket, TicketType}, upload::conflict::find_conflict}};
+
+pub const TITLE: u64 = 0x0004000000055D00;
+
+pub fn fs_storage() -> (TempDir, FsStorage) {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config::new(dir.path().to_path_buf());
+    let index = Arc::new(FileIndex::load(&config));
+
+    (dir, FsStorage::new(config, index))
+}
+
+fn path(path: &str) -> ClientPath {
+    ClientPath::parse(path).unwrap()
+}
+
+fn md5(data: &[u8]) -> String {
+    let mut hasher = Hasher::new(HashAlgorithm::MD5);
+    hasher.update(data);
+
+    hasher.finalize()
+}
+
+fn file(path: &str, size: u64, hash: Option<String>, mtime: Option<u64>) -> ClientFileInfo {
+    ClientFileInfo { path: path.to_string(), size, hash, kind: EntryKind::FILE, mtime }
+}
+
+fn folder(path: &str) -> ClientFileInfo {
+    ClientFileInfo { path: path.to_string(), size: 0, hash: None, kind: EntryKind::DIRECTORY, mtime: None }
+}
+
+fn paths(storage: &dyn Storage) -> Vec<String> {
+    storage.read_manifest(TITLE, Container::SAVE, None, HashAlgorithm::MD5).unwrap().unwrap().into_iter().map(|file| file.path).collect()
+}
+
+// an upload of files, committed on top of the container unless it's given a manifest
+pub async fn upload(storage: &dyn Storage, files: &[(&str, &[u8])]) -> Ticket {
+    let ticket = Ticket::new(TITLE, TicketType::UPLOAD, Container::SAVE, None, HashAlgorithm::MD5);
+    storage.begin_transaction(&ticket).unwrap();
+
+    for (name, data) in files {
+        let received = storage.write_file(ticket.id, &path(name), 0, &mut Cursor::new(data.to_vec()), 1024, HashAlgorithm::MD5).await.unwrap();
+        assert!(received.complete);
+        assert!(storage.finish_file(ticket.id, &path(name)).unwrap());
+    }
+
+    ticket
+}
+
+async fn committed_upload_is_listed(storage: &dyn Storage) {
+    let ticket = upload(storage, &[("/GameData.bin", b"hello")]).await;
+    assert_eq!(storage.list_titles().unwrap(), Vec::<u64>::new());
+
+    storage.commit_transaction(&ticket, "test").unwrap();
+    assert_eq!(storage.list_titles().unwrap(), vec![TITLE]);
+
+    let manifest = storage.read_manifest(TITLE, Container::SAVE, None, HashAlgorithm::MD5).unwrap().unwrap();
+    assert_eq!(manifest, vec![ServerFileInfo { path: "GameData.bin".to_string(), size: 5, hash: "5d41402abc4b2a76b9719d911017c592".to_string(), kind: EntryKind::FILE, mtime: None }]);
+    assert_eq!(storage.read_manifest(TITLE, Container::EXTDATA, None, HashAlgorithm::MD5).unwrap(), None);
+}
+
+async fn commit_mirrors_the_manifest(storage: &dyn Storage) {
+    let first = upload(storage, &[("/a", b"a"), ("/b", b"b")]).await;
+    storage.commit_transaction(&first, "test").unwrap();
+
+    // a is unchanged so it isn't sent again, b was deleted on the console
+    let mut second = upload(storage, &[("/c", b"c")]).await;
+    second.manifest = Some(vec![file("/a", 1, None, None), file("/c", 1, None, None)]);
+    storage.commit_transaction(&second, "test").unwrap();
+    assert_eq!(paths(storage), vec!["a", "c"]);
+
+    let mut third = upload(storage, &[]).await;
+    third.manifest = Some(vec![file("/missing", 1, None, None)]);
+    assert_eq!(storage.commit_transaction(&third, "test").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    let mut escaping = upload(storage, &[]).await;
+    escaping.manifest = Some(vec![file("/../a", 1, None, None)]);
+    assert_eq!(storage.commit_transaction(&escaping, "test").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(paths(storage), vec!["a", "c"]);
+}
+
+async fn reused_files_have_to_match_the_manifest(storage: &dyn Storage) {
+    let first = upload(storage, &[("/a", b"a")]).await;
+    storage.commit_transaction(&first, "test").unwrap();
+
+    // the client compared against a file that has since changed on the server
+    let mut second = upload(storage, &[]).await;
+    second.manifest = Some(vec![file("/a", 1, Some(md5(b"b")), None)]);
+    assert_eq!(storage.commit_transaction(&second, "test").unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    let mut third = upload(storage, &[]).await;
+    third.manifest = Some(vec![file("/a", 2, None, None)]);
+    assert_eq!(storage.commit_transaction(&third, "test").unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    let mut fourth = upload(storage, &[]).await;
+    fourth.manifest = Some(vec![file("/a", 1, Some(md5(b"a")), None)]);
+    storage.commit_transaction(&fourth, "test").unwrap();
+}
+
+async fn empty_manifest_clears_the_container(storage: &dyn Storage) {
+    let first = upload(storage, &[("/a", b"a")]).await;
+    storage.commit_transaction(&first, "test").unwrap();
+
+    let mut second = upload(storage, &[]).await;
+    second.manifest = Some(Vec::new());
+    storage.commit_transaction(&second, "test").unwrap();
+
+    assert_eq!(storage.read_manifest(TITLE, Container::SAVE, None, HashAlgorithm::MD5).unwrap(), Some(Vec::new()));
+    assert_eq!(storage.latest_revision(TITLE, Container::SAVE).unwrap().unwrap().files, Vec::new());
+}
+
+async fn commits_become_the_latest_revision(storage: &dyn Storage) {
+    assert_eq!(storage.latest_revision(TITLE, Container::SAVE).unwrap(), None);
+
+    let first = upload(storage, &[("/GameData.bin", b"hello")]).await;
+    let first = storage.commit_transaction(&first, "first").unwrap();
+    let second = upload(storage, &[("/GameData.bin", b"world")]).await;
+    storage.commit_transaction(&second, "second").unwrap();
+
+    let latest = storage.latest_revision(TITLE, Container::SAVE).unwrap().unwrap();
+    assert!(latest.id > first.id);
+    assert_eq!(latest.uploader, "second");
+    assert_eq!(latest.files, storage.read_manifest(TITLE, Container::SAVE, None, HashAlgorithm::MD5).unwrap().unwrap());
+    assert_eq!(storage.latest_revision(TITLE, Container::EXTDATA).unwrap(), None);
+}
+
+async fn committed_revision_is_the_next_base(storage: &dyn Storage) {
+    let first = upload(storage, &[("/GameData.bin", b"hello")]).await;
+    let base = storage.commit_transaction(&first, "test").unwrap().id;
+
+    let mut second = upload(storage, &[("/GameData.bin", b"world")]).await;
+    second.revision = Some(base);
+    assert!(find_conflict(storage, TITLE, Container::SAVE, second.revision, &[]).unwrap().is_none());
+    let next = storage.commit_transaction(&second, "test").unwrap().id;
+
+    // the first revision is stale now
+    assert!(find_conflict(storage, TITLE, Container::SAVE, Some(base), &[]).unwrap().is_some());
+    assert!(find_conflict(storage, TITLE, Container::SAVE, Some(next), &[]).unwrap().is_none());
+}
+
+async fn conflicts_are_kept_per_container(storage: &dyn Storage) {
+    let ticket = upload(storage, &[("/GameData.bin", b"hello")]).await;
+    storage.commit_transaction(&ticket, "old 3ds").unwrap();
+
+    let loser = storage.latest_revision(TITLE, Container::SAVE).unwrap().unwrap();
+    storage.add_conflict(TITLE, Container::SAVE, ConflictRevision::new(Container::SAVE, &loser, "new 3ds")).unwrap();
+
+    let conflicts = storage.list_conflicts(TITLE, Container::SAVE).unwrap();
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!((conflicts[0].device.as_str(), conflicts[0].replaced_by.as_str(), conflicts[0].revision), ("old 3ds", "new 3ds", loser.id));
+    assert_eq!(conflicts[0].files, loser.files);
+    assert!(storage.list_conflicts(TITLE, Container::EXTDATA).unwrap().is_empty());
+}
+
+async fn unchanged_files_follow_the_manifests_case(storage: &dyn Storage) {
+    let first = upload(storage, &[("/Folder/Save.bin", b"a")]).await;
+    storage.commit_transaction(&first, "test").unwrap();
+
+    let mut second = upload(storage, &[]).await;
+    second.manifest = Some(vec![file("/folder/SAVE.BIN", 1, None, None)]);
+    storage.commit_transaction(&second, "test").unwrap();
+    assert_eq!(paths(storage), vec!["folder/SAVE.BIN"]);
+}
+
+async fn only_empty_folders_are_listed(storage: &dyn Storage) {
+    let mut ticket = upload(storage, &[("/full/a", b"a")]).await;
+    ticket.manifest = Some(vec![folder("/full"), file("/full/a", 1, None, None), folder("/empty")]);
+    storage.commit_transaction(&ticket, "test").unwrap();
+
+    let manifest = storage.read_manifest(TITLE, Container::SAVE, None, HashAlgorithm::MD5).unwrap().unwrap();
+    assert_eq!(manifest.iter().map(|file| (file.path.as_str(), file.kind)).collect::<Vec<_>>(), vec![("empty", EntryKind::DIRECTORY), ("full/a", EntryKind::FILE)]);
+
+    // a later upload without the folder removes it
+    let mut second = upload(storage, &[]).await;
+    second.manifest = Some(vec![file("/full/a", 1, None, None)]);
+    storage.commit_transaction(&second, "test").unwrap();
+    assert_eq!(paths(storage), vec!["full/a"]);
+}
+
+async fn mtimes_follow_unchanged_files(storage: &dyn Storage) {
+    let mut first = upload(storage, &[("/a", b"a"), ("/b", b"b")]).await;
+    first.manifest = Some(vec![file("/a", 1, None, Some(100)), file("/b", 1, None, Some(200))]);
+    storage.commit_transaction(&first, "test").unwrap();
+
+    // a changed file without a declared mtime loses it, an unchanged one keeps it
+    let mut second = upload(storage, &[("/b", b"c")]).await;
+    second.manifest = Some(vec![file("/a", 1, None, None), file("/b", 1, None, None)]);
+    storage.commit_transaction(&second, "test").unwrap();
+
+    let manifest = storage.read_manifest(TITLE, Container::SAVE, None, HashAlgorithm::SHA256).unwrap().unwrap();
+    assert_eq!(manifest.iter().map(|file| (file.path.as_str(), file.mtime)).collect::<Vec<_>>(), vec![("a", Some(100)), ("b", None)]);
+    assert_eq!(storage.latest_revision(TITLE, Container::SAVE).unwrap().unwrap().files[0].mtime, Some(100));
+}
+
+async fn aborted_upload_changes_nothing(storage: &dyn Storage) {
+    let ticket = upload(storage, &[("/GameData.bin", b"hello")]).await;
+
+    storage.abort_transaction(ticket.id).unwrap();
+    assert!(storage.commit_transaction(&ticket, "test").is_err());
+    assert_eq!(storage.read_manifest(TITLE, Container::SAVE, None, HashAlgorithm::MD5).unwrap(), None);
+}
+
+async fn download_is_a_snapshot(storage: &dyn Storage) {
+    let first = upload(storage, &[("/GameData.bin", b"first")]).await;
+    storage.commit_transaction(&first, "test").unwrap();
+
+    let download = Ticket::new(TITLE, TicketType::DOWNLOAD, Container::SAVE, None, HashAlgorithm::MD5);
+    storage.begin_transaction(&download).unwrap();
+
+    let second = upload(storage, &[("/GameData.bin", b"second")]).await;
+    storage.commit_transaction(&second, "test").unwrap();
+
+    let (mut file, size) = storage.open_file(download.id, &path("/GameData.bin")).await.unwrap();
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).await.unwrap();
+
+    assert_eq!(size, 5);
+    assert_eq!(data, b"first");
+}
+
+async fn download_of_missing_container_fails(storage: &dyn Storage) {
+    let download = Ticket::new(TITLE, TicketType::DOWNLOAD, Container::EXTDATA, None, HashAlgorithm::MD5);
+    assert_eq!(storage.begin_transaction(&download).unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+async fn chunks_are_joined_and_limited(storage: &dyn Storage) {
+    let ticket = Ticket::new(TITLE, TicketType::UPLOAD, Container::SAVE, None, HashAlgorithm::CRC32);
+    storage.begin_transaction(&ticket).unwrap();
+
+    let first = storage.write_file(ticket.id, &path("/a"), 0, &mut Cursor::new(b"hel".to_vec()), 4, HashAlgorithm::CRC32).await.unwrap();
+    assert_eq!((first.size, first.complete), (3, true));
+
+    // a chunk that is resent replaces everything after its offset
+    storage.write_file(ticket.id, &path("/a"), 3, &mut Cursor::new(b"xx".to_vec()), 4, HashAlgorithm::CRC32).await.unwrap();
+    storage.write_file(ticket.id, &path("/a"), 3, &mut Cursor::new(b"lo".to_vec()), 4, HashAlgorithm::CRC32).await.unwrap();
+    assert_eq!(storage.hash_partial_file(ticket.id, &path("/a"), HashAlgorithm::CRC32).unwrap(), "3610a686");
+
+    let too_large = storage.write_file(ticket.id, &path("/b"), 0, &mut Cursor::new(b"hello".to_vec()), 4, HashAlgorithm::CRC32).await.unwrap();
+    assert!(!too_large.complete);
+}
+
+// a test per storage for every test above
+macro_rules! storage_tests {
+    ($($name:ident),*) => {
+        mod memory {
+            use super::*;
+
+            $(
+                #[rocket::async_test]
+                async fn $name() {
+                    super::$name(&MemoryStorage::default()).await
+                }
+            )*
+        }
+
+        mod filesystem {
+            use super::*;
+
+            $(
+                #[rocket::async_test]
+                async fn $name() {
+                    let (_dir, storage) = fs_storage();
+                    super::$name(&storage).await
+                }
+            )*
+        }
+    };
+}
+
+storage_tests!(
+    committed_upload_is_listed,
+    commit_mirrors_the_manifest,
+    reused_files_have_to_match_the_manifest,
+    empty_manifest_clears_the_container,
+    commits_become_the_latest_revision,
+    committed_revision_is_the_next_base,
+    conflicts_are_kept_per_container,
+    unchanged_files_follow_the_manifests_case,
+    only_empty_folders_are_listed,
+    mtimes_follow_unchanged_files,
+    aborted_upload_changes_nothing,
+    download_is_a_snapshot,
+    download_of_missing_container_fails,
+    chunks_are_joined_and_limited
+);
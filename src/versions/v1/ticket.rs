@@ -1,15 +1,18 @@
-use std::{collections::HashMap, env, fs, io, path::{Path, PathBuf}, str::FromStr, sync::{Arc, Mutex}};
+use std::{collections::HashMap, fmt, fs::{self, File}, io::{self, BufReader}, path::{Path, PathBuf}, str::FromStr, sync::{Arc, Mutex}, time::{Duration, SystemTime, UNIX_EPOCH}};
 
-use fs_extra::dir::remove;
+use rocket::tokio;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+use crate::{config::Config, versions::v1::{client_path::{ClientPath, path_key}, file_info::{ClientFileInfo, EntryKind, HashAlgorithm, ServerFileInfo, hashes_match}, storage::{Storage, Store}}};
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
 pub enum TicketType {
     UPLOAD,
     DOWNLOAD
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
 pub enum Container {
     SAVE,
     EXTDATA
@@ -28,54 +31,289 @@ impl FromStr for Container {
     }
 }
 
-impl ToString for Container {
-    fn to_string(&self) -> String {
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Container::SAVE    => "SAVE".to_string(),
-            Container::EXTDATA => "EXTDATA".to_string()
+            Container::SAVE    => write!(f, "SAVE"),
+            Container::EXTDATA => write!(f, "EXTDATA")
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+// tickets are persisted as <tickets path>/<id>.json next to their staging directory, so they survive restarts
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct Ticket {
     pub id: Uuid,
     pub title_id: u64,
     pub kind: TicketType,
-    pub container: Container
+    pub container: Container,
+    // downloads: the history revision the download was issued against, None for the live container
+    // uploads: the revision the client based its files on, checked again before committing
+    pub revision: Option<u64>,
+    // uploads that are committed even if they conflict, the revision they replace is kept as a conflict revision
+    #[serde(default)]
+    pub force: bool,
+    // unix timestamps in seconds, tickets inactive for longer than the configured ttl are reaped
+    pub created: u64,
+    pub last_activity: u64,
+    // used for every hash the client sends or receives with this ticket
+    #[serde(default)]
+    pub algorithm: HashAlgorithm,
+    // uploads: every file the client has, the container is made to match it exactly when the upload is committed
+    // downloads: every file of the snapshot, as the server spells it
+    // None for tickets from before manifests were kept, uploads are committed on top of the container instead
+    #[serde(default)]
+    pub manifest: Option<Vec<ClientFileInfo>>,
+    // the files an upload asked the client for, and the ones received so far
+    #[serde(default)]
+    pub requested_files: Vec<String>,
+    #[serde(default)]
+    pub received_files: Vec<ServerFileInfo>,
+    // chunked uploads that haven't been completed yet
+    #[serde(default)]
+    pub partial_files: Vec<PartialFile>,
+    // downloads: the md5 of every file by path key, sent as its etag without reading the file again
+    #[serde(default)]
+    pub etags: HashMap<String, String>
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct PartialFile {
+    pub path: String,
+    // bytes confirmed so far, the next chunk has to start here
+    pub offset: u64
+}
+
+impl Ticket {
+    pub fn new(title_id: u64, kind: TicketType, container: Container, revision: Option<u64>, algorithm: HashAlgorithm) -> Self {
+        let now = unix_time();
+        Ticket { id: Uuid::new_v4(), title_id, kind, container, revision, force: false, created: now, last_activity: now, algorithm, manifest: None, requested_files: Vec::new(), received_files: Vec::new(), partial_files: Vec::new(), etags: HashMap::new() }
+    }
+
+    pub fn touch(&mut self) {
+        self.last_activity = unix_time();
+    }
+
+    pub fn receive(&mut self, file: ServerFileInfo) {
+        self.partial_files.retain(|partial| partial.path != file.path);
+        self.received_files.retain(|received| received.path != file.path);
+        self.received_files.push(file);
+    }
+
+    pub fn partial_offset(&self, path: &str) -> u64 {
+        self.partial_files.iter().find(|partial| partial.path == path).map(|partial| partial.offset).unwrap_or(0)
+    }
+
+    pub fn set_partial_offset(&mut self, path: &str, offset: u64) {
+        self.partial_files.retain(|partial| partial.path != path);
+        if offset > 0 {
+            self.partial_files.push(PartialFile { path: path.to_string(), offset });
+        }
+    }
+
+    // requested files that haven't been received yet
+    pub fn missing_files(&self) -> Vec<String> {
+        self.requested_files.iter().filter(|path| !self.received_files.iter().any(|received| received.path == **path)).cloned().collect()
+    }
+
+    // the manifest entry for path in whatever case the client used, None if it isn't in the manifest
+    // tickets from before manifests were kept accept any path
+    pub fn manifest_path(&self, path: &ClientPath) -> Option<ClientPath> {
+        let Some(manifest) = &self.manifest else { return Some(path.clone()) };
+
+        let file = manifest.iter().find(|file| file.kind == EntryKind::FILE && path_key(&file.path) == path.key())?;
+        ClientPath::parse(&file.path).ok()
+    }
+
+    // the mtimes the client declared by path key, legacy tickets without a manifest have none
+    pub fn mtimes(&self) -> HashMap<String, u64> {
+        self.manifest.iter().flatten().filter_map(|file| Some((path_key(&file.path), file.mtime?))).collect()
+    }
+
+    // size and hash have to be what the client declared, files without a declared hash only have their size checked
+    pub fn matches_manifest(&self, path: &str, size: u64, hash: &str) -> bool {
+        let Some(manifest) = &self.manifest else { return true };
+
+        let Some(file) = manifest.iter().find(|file| file.path == path) else { return false };
+        file.size == size && file.hash.as_ref().is_none_or(|declared| hashes_match(declared, hash))
+    }
+
+    // received files that don't match the manifest, e.g. because they were cut off by a restart
+    pub fn mismatched_files(&self) -> Vec<String> {
+        self.received_files.iter().filter(|file| !self.matches_manifest(&file.path, file.size, &file.hash)).map(|file| file.path.clone()).collect()
+    }
 }
 
 pub type Tickets = Arc<Mutex<HashMap<Uuid, Ticket>>>;
 
-pub fn tickets_path() -> PathBuf {
-    env::temp_dir().join("SaveSyncdv1")
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+// kept inside the data directory so staging survives reboots, the leading dot keeps it out of the title listing
+pub fn tickets_path(config: &Config) -> PathBuf {
+    config.data_directory().join(".tickets")
+}
+
+pub fn ticket_path(config: &Config, ticket: Uuid) -> PathBuf {
+    tickets_path(config).join(ticket.hyphenated().to_string())
 }
 
-pub fn ticket_path(ticket: Uuid) -> PathBuf {
-    tickets_path().join(ticket.hyphenated().to_string())
+// chunked uploads are assembled here, outside of the staging directory until they are complete
+pub fn ticket_partial_path(config: &Config, ticket: Uuid) -> PathBuf {
+    tickets_path(config).join(format!("{}.partial", ticket.hyphenated()))
 }
 
-pub fn clear_tickets_path() -> Result<(), fs_extra::error::Error> {
-    remove(tickets_path())
+fn ticket_state_path(config: &Config, ticket: Uuid) -> PathBuf {
+    tickets_path(config).join(format!("{}.json", ticket.hyphenated()))
 }
 
-pub fn clear_ticket_path(ticket: Uuid) -> Result<(), fs_extra::error::Error> {
-    remove(ticket_path(ticket))
+pub fn save_ticket(config: &Config, ticket: &Ticket) -> io::Result<()> {
+    fs::create_dir_all(tickets_path(config))?;
+
+    // written to a temporary file first so a crash never leaves a truncated ticket behind
+    let path = ticket_state_path(config, ticket.id);
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_string_pretty(ticket).map_err(io::Error::other)?)?;
+    fs::rename(temp_path, path)
 }
 
-pub fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
+// the staging files of a ticket belong to the storage, see Storage::abort_transaction
+pub fn delete_ticket(config: &Config, ticket: Uuid) -> io::Result<()> {
+    match fs::remove_file(ticket_state_path(config, ticket)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(())
+    }
+}
+
+// reloads the tickets saved before the last shutdown, anything in the tickets path without a readable ticket is removed
+pub fn load_tickets(config: &Config) -> HashMap<Uuid, Ticket> {
+    let mut out: HashMap<Uuid, Ticket> = HashMap::new();
+    let Ok(entries) = fs::read_dir(tickets_path(config)) else { return out };
+
+    let now = unix_time();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+
+        let Ok(mut ticket) = File::open(&path).map_err(|_| ()).and_then(|file| serde_json::from_reader::<_, Ticket>(BufReader::new(file)).map_err(|_| ())) else { continue; };
+        if !ticket_path(config, ticket.id).is_dir() {
+            continue;
+        }
+
+        // files that went missing or were cut off by the restart have to be sent again
+        let staging_path = ticket_path(config, ticket.id);
+        ticket.received_files.retain(|file| {
+            fs::metadata(staging_path.join(file.path.strip_prefix("/").unwrap_or(&file.path))).is_ok_and(|metadata| metadata.len() == file.size)
+        });
+
+        let partial_path = ticket_partial_path(config, ticket.id);
+        ticket.partial_files.retain(|file| {
+            fs::metadata(partial_path.join(file.path.strip_prefix("/").unwrap_or(&file.path))).is_ok_and(|metadata| metadata.len() >= file.offset)
+        });
+
+        // give clients a full ttl to resume after the restart
+        ticket.last_activity = now;
+        out.insert(ticket.id, ticket);
+    }
+
+    for entry in fs::read_dir(tickets_path(config)).into_iter().flatten().flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let id = name.strip_suffix(".json").or(name.strip_suffix(".partial")).unwrap_or(&name);
+        if Uuid::try_parse(id).is_ok_and(|id| out.contains_key(&id)) {
+            continue;
+        }
+
+        let removed = match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => fs::remove_dir_all(entry.path()),
+            _ => fs::remove_file(entry.path())
+        };
+
+        if removed.is_err() {
+            println!("Failed to remove stale ticket data {}", entry.path().display());
+        }
+    }
+
+    for ticket in out.values() {
+        if save_ticket(config, ticket).is_err() {
+            println!("Failed to save ticket {}", ticket.id.hyphenated());
+        }
+
+        println!("Restored {:?} ticket {} for {:X} ({})", ticket.kind, ticket.id.hyphenated(), ticket.title_id, ticket.container);
+    }
+
+    out
+}
+
+// cancels tickets that have been inactive for longer than ttl, removing their staging directories
+pub fn reap_expired_tickets(tickets: &Tickets, config: &Config, storage: &dyn Storage, ttl: Duration) {
+    let now = unix_time();
+    let expired: Vec<Ticket> = {
+        let Ok(mut ticket_map) = tickets.lock() else { return };
+        let expired: Vec<Ticket> = ticket_map.values().filter(|ticket| now.saturating_sub(ticket.last_activity) > ttl.as_secs()).cloned().collect();
+        for ticket in &expired {
+            ticket_map.remove(&ticket.id);
+        }
+
+        expired
+    };
+
+    for ticket in expired {
+        if storage.abort_transaction(ticket.id).is_err() {
+            println!("Failed to clear ticket path {}", ticket.id.hyphenated());
+        }
+
+        if delete_ticket(config, ticket.id).is_err() {
+            println!("Failed to delete ticket {}", ticket.id.hyphenated());
+        }
+
+        println!("Reaped expired {:?} ticket {} for {:X} ({}), inactive for {} seconds", ticket.kind, ticket.id.hyphenated(), ticket.title_id, ticket.container, now.saturating_sub(ticket.last_activity));
+    }
+}
+
+pub async fn reap_tickets_task(tickets: Tickets, config: Config, storage: Store) {
+    let ttl = config.ticket_ttl();
+    let mut interval = tokio::time::interval((ttl / 4).clamp(Duration::from_secs(1), Duration::from_secs(60)));
+    loop {
+        interval.tick().await;
+        reap_expired_tickets(&tickets, &config, storage.as_ref(), ttl);
+    }
+}
+
+// hard links every file instead of copying it when the filesystem allows it
+// existing files in dst are replaced instead of written to, so files that are linked elsewhere never change
+pub fn link_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
     fs::create_dir_all(&dst)?;
     for entry in fs::read_dir(src)? {
         let entry = entry?;
         let file_type = entry.file_type()?;
         
         if file_type.is_dir() {
-            copy_dir_all(entry.path(), dst.as_ref().join(entry.file_name()))?;
+            link_dir_all(entry.path(), dst.as_ref().join(entry.file_name()))?;
             continue;
         }
 
-        fs::copy(entry.path(), dst.as_ref().join(entry.file_name()))?;
+        link_file(entry.path(), dst.as_ref().join(entry.file_name()))?;
     }
     
     Ok(())
 }
+
+pub fn link_file(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
+    if let Some(parent) = dst.as_ref().parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match fs::remove_file(&dst) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+
+    if fs::hard_link(&src, &dst).is_err() {
+        fs::copy(&src, &dst)?;
+    }
+
+    Ok(())
+}
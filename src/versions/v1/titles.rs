@@ -1,52 +1,50 @@
-use std::{collections::HashMap, fs, path::Path};
+use std::collections::BTreeMap;
 
-use fs_extra::dir::{DirOptions, get_dir_content, get_dir_content2};
 use rocket::State;
 use serde::Serialize;
 
-use crate::{config::Config, versions::v1::file_info::{ServerFileInfo, file_hash}};
+use crate::{devices::Device, error::ApiError, versions::v1::{conditional::{Conditional, Preconditions}, file_info::{HashAlgorithm, ServerFileInfo}, history::ConflictRevision, locks::TitleLocks, storage::{Store, blocking}, ticket::Container}};
 
 #[derive(Serialize)]
 struct TitleInfo {
     save: Vec<ServerFileInfo>,
-    extdata: Vec<ServerFileInfo>
+    extdata: Vec<ServerFileInfo>,
+    // revisions replaced by forced uploads, restoring one picks it
+    conflicts: Vec<ConflictRevision>
 }
 
-type TitlesResponse = HashMap<u64, TitleInfo>;
+// ordered so the same titles always serialize the same, which keeps the ETag stable
+type TitlesResponse = BTreeMap<u64, TitleInfo>;
 
-fn get_dir_info(dir: String) -> Vec<ServerFileInfo> {
-    let path = Path::new(&dir).to_path_buf();
-    let mut out: Vec<ServerFileInfo> = Vec::new();
-
-    if path.try_exists().unwrap_or(false) {
-        for file in get_dir_content(path).expect("Failed to get title save content").files {
-            let Some(path) = file.strip_prefix(&dir) else { continue; };
-            let Ok(metadata) = fs::metadata(&file) else { continue; };
-            let Ok(hash) = file_hash(Path::new(&file)) else { continue; };
+#[get("/v1/titles?<algorithm>")]
+pub async fn titles(_device: Device, storage: &State<Store>, locks: &State<TitleLocks>, preconditions: Preconditions, algorithm: Option<HashAlgorithm>) -> Result<Conditional<String>, ApiError> {
+    let body = titles_json(storage, locks, algorithm.unwrap_or_default()).await?;
+    Ok(Conditional::new(&preconditions, format!("{:x}", md5::compute(&body)), body))
+}
 
-            out.push(ServerFileInfo { path: path.to_string(), size: metadata.len(), hash });
-        }
-    }
+async fn titles_json(storage: &Store, locks: &TitleLocks, algorithm: HashAlgorithm) -> std::io::Result<String> {
+    let mut out: TitlesResponse = BTreeMap::new();
 
-    out
-}
+    for id in storage.list_titles()? {
+        // a title that is being committed is listed once the commit is done
+        let _save = locks.read(id, Container::SAVE).await;
+        let _extdata = locks.read(id, Container::EXTDATA).await;
 
-#[get("/v1/titles")]
-pub async fn titles(config: &State<Config>) -> String {
-    let mut out: TitlesResponse = HashMap::new();
+        let info = blocking(storage, move |storage| {
+            let mut conflicts = storage.list_conflicts(id, Container::SAVE)?;
+            conflicts.extend(storage.list_conflicts(id, Container::EXTDATA)?);
 
-    let data_dir = config.data_directory();
-    let data_dir_str = format!("{}/", data_dir.to_str().expect("Failed to get string of data directory"));
+            Ok(TitleInfo {
+                save: storage.read_manifest(id, Container::SAVE, None, algorithm)?.unwrap_or_default(),
+                extdata: storage.read_manifest(id, Container::EXTDATA, None, algorithm)?.unwrap_or_default(),
+                conflicts
+            })
+        }).await?;
 
-    let Ok(contents) = get_dir_content2(data_dir, &DirOptions { depth: 1 }) else { return "{}".to_string() };
-    for directory in contents.directories {
-        let Ok(id) = u64::from_str_radix(directory.strip_prefix(&data_dir_str).unwrap_or(""), 16) else { continue; };
-        
-        let info = TitleInfo { save: get_dir_info(format!("{}/save/", directory)), extdata: get_dir_info(format!("{}/extdata/", directory)) };
-        if !info.save.is_empty() || !info.extdata.is_empty() {
+        if !info.save.is_empty() || !info.extdata.is_empty() || !info.conflicts.is_empty() {
             out.insert(id, info);
         }
     }
 
-    return serde_json::to_string_pretty(&out).expect("Failed to stringify titles");
-}
\ No newline at end of file
+    serde_json::to_string_pretty(&out).map_err(std::io::Error::other)
+}
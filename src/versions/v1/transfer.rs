@@ -0,0 +1,61 @@
+use std::{io::{self, SeekFrom}, path::Path, pin::Pin, task::{Context, Poll}};
+
+use rocket::{data::{Data, DataStream, ToByteUnit}, tokio::{self, fs::{self, OpenOptions}, io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite}}};
+
+use crate::versions::v1::file_info::{HashAlgorithm, Hasher};
+
+// passes writes through to the inner writer, hashing everything that was written
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Hasher
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for HashingWriter<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.hasher.update(&buf[..written]);
+        }
+
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ReceivedData {
+    pub size: u64,
+    // hash of the received bytes only, not of the whole file
+    pub hash: String,
+    // false if the body was larger than the limit
+    pub complete: bool
+}
+
+// opened one byte past the limit, so a body that is too large can be told apart from one that is exactly the limit
+pub fn open_body(data: Data<'_>, limit: u64) -> DataStream<'_> {
+    data.open((limit + 1).bytes())
+}
+
+// streams a body into a file starting at offset, anything after offset is discarded first
+pub async fn receive_file(body: &mut (dyn AsyncRead + Send + Unpin), limit: u64, path: &Path, offset: u64, algorithm: HashAlgorithm) -> io::Result<ReceivedData> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    let mut file = OpenOptions::new().create(true).write(true).truncate(false).open(path).await?;
+    file.set_len(offset).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+
+    let mut writer = HashingWriter { inner: file, hasher: Hasher::new(algorithm) };
+    let written = tokio::io::copy(&mut body.take(limit + 1), &mut writer).await?;
+    writer.inner.sync_data().await?;
+
+    Ok(ReceivedData { size: written, hash: writer.hasher.finalize(), complete: written <= limit })
+}
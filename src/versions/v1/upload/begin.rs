@@ -1,66 +1,78 @@
-use std::str::FromStr;
+use std::{collections::HashMap, str::FromStr};
 
-use fs_extra::dir::create_all;
-use rocket::{State, http::Status, serde::{Deserialize, json::Json}};
+use rocket::{State, serde::{Deserialize, json::Json}};
 use serde::Serialize;
-use uuid::Uuid;
-use crate::{config::Config, v1::ticket::{Container, Ticket, TicketType, Tickets, ticket_path}, versions::v1::file_info::{ClientFileInfo, file_hash}};
+use crate::{config::Config, devices::Device, error::ApiError, v1::ticket::{Container, Ticket, TicketType, Tickets}, versions::v1::{Synced, client_path::{ClientPath, entry_keys, find_collisions, path_key}, file_info::{ClientFileInfo, EntryKind, HashAlgorithm, hashes_match}, locks::TitleLocks, storage::{Store, blocking}, ticket::save_ticket, upload::conflict::find_conflict}};
 
 #[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct BeginBody {
     id: u64,
     container: String,
-    files: Vec<ClientFileInfo>
+    files: Vec<ClientFileInfo>,
+    // the revision the client last synced with, from download begin or a previous upload
+    #[serde(default)]
+    base_revision: Option<u64>,
+    // commit even if the server has moved on, the server's revision is kept as a conflict revision
+    #[serde(default)]
+    force: bool,
+    #[serde(default)]
+    algorithm: HashAlgorithm
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct BeginResponse {
     ticket: String,
-    files: Vec<String>
+    files: Vec<String>,
+    algorithm: HashAlgorithm
+}
+
+// nothing has to be uploaded when the server already has the client's files
+#[derive(Debug, Responder)]
+pub enum BeginResult {
+    Started(Json<BeginResponse>),
+    UpToDate(Synced)
 }
 
 #[post("/v1/upload/begin", format = "application/json", data = "<data>")]
-pub fn upload_begin(tickets: &State<Tickets>, config: &State<Config>, data: Json<BeginBody>) -> Result<Json<BeginResponse>, Status> {
-    let container = Container::from_str(&data.container).map_err(|_| Status::BadRequest)?;
-    if data.files.is_empty() {
-        return Err(Status::BadRequest)
+pub async fn upload_begin(_device: Device, tickets: &State<Tickets>, locks: &State<TitleLocks>, config: &State<Config>, storage: &State<Store>, data: Json<BeginBody>) -> Result<BeginResult, ApiError> {
+    let container = Container::from_str(&data.container).map_err(|_| ApiError::BadRequest(format!("{} isn't a container", data.container)))?;
+    // compared and stored in their canonical form from here on
+    let mut manifest = Vec::new();
+    let mut paths = Vec::new();
+    for file in &data.files {
+        let path = ClientPath::parse(&file.path).map_err(|err| ApiError::InvalidPath(file.path.clone(), err))?;
+        manifest.push(ClientFileInfo { path: path.to_string(), ..file.clone() });
+        paths.push((path, file.kind));
     }
 
-    let mut ticket_map = tickets.lock().map_err(|_| Status::InternalServerError)?;
-
-    let ticket_id = Uuid::new_v4();
-    let ticket = Ticket { id: ticket_id, title_id: data.id, kind: TicketType::UPLOAD, container: container };
-
-    create_all(ticket_path(ticket_id), false).expect("Failed to create directories for ticket");
-    ticket_map.insert(ticket_id, ticket);
-
-    let title_path = config.data_directory().join(format!("{:X}", data.id));
-    let container_path = title_path.join(container.to_string().to_lowercase());
+    let collisions = find_collisions(&paths);
+    if !collisions.is_empty() {
+        return Err(ApiError::PathCollision(collisions))
+    }
 
-    if !container_path.exists() {
-        return Ok(Json(BeginResponse { ticket: ticket.id.hyphenated().to_string(), files: data.files.iter().map(|f| f.path.clone()).collect() }))
+    // other uploads can't be committed while the server files are compared
+    let _title = locks.read(data.id, container).await;
+    let (id, algorithm, base_revision, client_files) = (data.id, data.algorithm, data.base_revision, manifest.clone());
+    let conflict = blocking(storage, move |storage| find_conflict(storage, id, container, base_revision, &client_files)).await?;
+    if let Some(conflict) = conflict.filter(|_| !data.force) {
+        return Err(ApiError::Conflict(Box::new(conflict)))
     }
 
-    let mut files: Vec<String> = data.files.iter().map(|f| f.path.clone()).collect();
+    // only files are uploaded, folders are created from the manifest
+    let mut files: Vec<String> = manifest.iter().filter(|f| f.kind == EntryKind::FILE).map(|f| f.path.clone()).collect();
     files.sort();
     files.dedup();
 
-    for file in &data.files {
-        let Some(stripped_path) = file.path.strip_prefix("/") else { continue; };
-        let file_path = container_path.join(stripped_path);
+    let server_files = blocking(storage, move |storage| storage.read_manifest(id, container, None, algorithm)).await?.unwrap_or_default();
+    let server_entries = entry_keys(server_files.iter().map(|file| (file.path.as_str(), file.kind)));
+    let existing: HashMap<String, (u64, String)> = server_files.into_iter().filter(|file| file.kind == EntryKind::FILE).map(|file| (path_key(&file.path), (file.size, file.hash))).collect();
 
-        if !file_path.exists() {
-            continue;
-        }
-
-        let Ok(metadata) = file_path.metadata() else { continue; };
-
-        if file.size != metadata.len() {
-            continue;
-        }
+    // files that only differ in case are still the same file, and are renamed when the upload is committed
+    for file in manifest.iter().filter(|f| f.kind == EntryKind::FILE) {
+        let Some((size, hash)) = existing.get(&path_key(&file.path)) else { continue; };
 
-        let Ok(hash) = file_hash(&file_path) else { continue; };
-        if file.hash != Some(hash) {
+        if file.size != *size || !file.hash.as_deref().is_some_and(|declared| hashes_match(declared, hash)) {
             continue;
         }
 
@@ -69,9 +81,23 @@ pub fn upload_begin(tickets: &State<Tickets>, config: &State<Config>, data: Json
         }
     }
 
-    if files.is_empty() {
-        return Err(Status::NoContent)
+    // with nothing to upload the ticket is still needed if folders or files were removed or added
+    let client_entries = entry_keys(manifest.iter().map(|file| (file.path.as_str(), file.kind)));
+    if files.is_empty() && client_entries == server_entries {
+        let revision = blocking(storage, move |storage| storage.latest_revision(id, container)).await?.map(|revision| revision.id);
+        return Ok(BeginResult::UpToDate(Synced(revision)))
     }
 
-    Ok(Json(BeginResponse { ticket: ticket.id.hyphenated().to_string(), files }))
+    let mut ticket = Ticket::new(data.id, TicketType::UPLOAD, container, data.base_revision, data.algorithm);
+    ticket.force = data.force;
+    ticket.manifest = Some(manifest);
+    ticket.requested_files = files.clone();
+
+    storage.begin_transaction(&ticket)?;
+    save_ticket(config, &ticket)?;
+
+    let ticket_id = ticket.id;
+    tickets.lock()?.insert(ticket_id, ticket);
+
+    Ok(BeginResult::Started(Json(BeginResponse { ticket: ticket_id.hyphenated().to_string(), files, algorithm: data.algorithm })))
 }
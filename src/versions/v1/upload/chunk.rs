@@ -0,0 +1,107 @@
+use rocket::{State, data::Data, serde::json::Json};
+use serde::Serialize;
+use uuid::Uuid;
+use crate::{config::Config, devices::Device, error::ApiError, v1::ticket::Tickets, versions::v1::{client_path::ClientPath, file_info::{EntryKind, ServerFileInfo, hashes_match}, storage::{Store, blocking}, ticket::{TicketType, save_ticket}, transfer::open_body}};
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ChunkResponse {
+    path: String,
+    offset: u64,
+    complete: bool
+}
+
+// writes part of a file, chunks have to arrive in order starting at the last confirmed offset
+// size and hash describe the whole file, which is verified once the last chunk arrives
+// both hashes use the algorithm the ticket was started with
+#[put("/v1/upload/<ticket>/chunk?<path>&<offset>&<chunk_hash>&<size>&<hash>", format = "application/octet-stream", data = "<data>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_chunk(_device: Device, tickets: &State<Tickets>, config: &State<Config>, storage: &State<Store>, ticket: &str, path: &str, offset: u64, chunk_hash: &str, size: u64, hash: &str, data: Data<'_>) -> Result<Json<ChunkResponse>, ApiError> {
+    let uuid = Uuid::try_parse(ticket).map_err(|_| ApiError::MalformedTicket(ticket.to_string()))?;
+    let client_path = ClientPath::parse(path).map_err(|err| ApiError::InvalidPath(path.to_string(), err))?;
+
+    let (path, algorithm) = {
+        let mut ticket_map = tickets.lock()?;
+
+        let Some(ticket) = ticket_map.get_mut(&uuid) else { return Err(ApiError::UnknownTicket(ticket.to_string())) };
+        if ticket.kind != TicketType::UPLOAD {
+            return Err(ApiError::WrongTicketType(ticket.id.hyphenated().to_string()))
+        }
+
+        ticket.touch();
+        // stored as the manifest spells it, whatever case the client used here
+        let Some(path) = ticket.manifest_path(&client_path) else { return Err(ApiError::NotRequested(client_path.to_string())) };
+        let name = path.to_string();
+
+        // the limit is for whole files, chunking only splits them into smaller requests
+        if size > config.file_limit() {
+            return Err(ApiError::FileTooLarge(name))
+        }
+
+        // the whole file has to be the one declared in the manifest, which is checked again once it's complete
+        if !ticket.matches_manifest(&name, size, hash) {
+            return Err(ApiError::ManifestMismatch(name))
+        }
+
+        // the client has to resume from the confirmed offset, see GET /v1/upload/<ticket>
+        let confirmed = ticket.partial_offset(&name);
+        if offset != confirmed {
+            return Err(ApiError::WrongOffset(name, confirmed))
+        }
+
+        (path, ticket.algorithm)
+    };
+
+    let name = path.to_string();
+
+    // anything past the confirmed offset is from a chunk that never got confirmed, and is overwritten
+    let received = storage.write_file(uuid, &path, offset, &mut open_body(data, config.file_limit()), config.file_limit(), algorithm).await?;
+    if !received.complete {
+        return Err(ApiError::FileTooLarge(name))
+    }
+
+    if offset + received.size > size || !hashes_match(&received.hash, chunk_hash) {
+        return Err(ApiError::ChunkMismatch(name))
+    }
+
+    let new_offset = offset + received.size;
+    let complete = new_offset == size;
+
+    // the whole file is hashed before the tickets are locked again, every other request needs them too
+    let received_hash = match complete {
+        true => {
+            let path = path.clone();
+            Some(blocking(storage, move |storage| storage.hash_partial_file(uuid, &path, algorithm)).await?)
+        },
+        false => None
+    };
+
+    let mut ticket_map = tickets.lock()?;
+    let Some(ticket) = ticket_map.get_mut(&uuid) else { return Err(ApiError::UnknownTicket(uuid.hyphenated().to_string())) };
+    let confirmed = ticket.partial_offset(&name);
+    if offset != confirmed {
+        return Err(ApiError::WrongOffset(name, confirmed))
+    }
+
+    match received_hash {
+        None => ticket.set_partial_offset(&name, new_offset),
+        Some(received_hash) if !hashes_match(&received_hash, hash) => {
+            ticket.set_partial_offset(&name, 0);
+            let _ = storage.discard_file(uuid, &path);
+            if save_ticket(config, ticket).is_err() {
+                println!("Failed to save ticket {}", ticket.id.hyphenated());
+            }
+
+            return Err(ApiError::ManifestMismatch(name))
+        },
+        Some(received_hash) => {
+            storage.finish_file(uuid, &path)?;
+            ticket.receive(ServerFileInfo { path: name.clone(), size, hash: received_hash, kind: EntryKind::FILE, mtime: None });
+        }
+    }
+
+    if save_ticket(config, ticket).is_err() {
+        println!("Failed to save ticket {}", ticket.id.hyphenated());
+    }
+
+    Ok(Json(ChunkResponse { path: name, offset: new_offset, complete }))
+}
@@ -0,0 +1,38 @@
+use std::io;
+
+use serde::Serialize;
+
+use crate::versions::v1::{file_info::ClientFileInfo, history::Revision, storage::Storage, ticket::Container};
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConflictResponse {
+    // the revision the client last synced with
+    base_revision: u64,
+    // what the server has now, None if the container has no revisions anymore
+    pub server: Option<Box<Revision>>,
+    // what the client wants to upload
+    client: Vec<ClientFileInfo>
+}
+
+// Some if the server has moved on since the client last synced
+// uploads without a base revision are always accepted, like before conflicts were detected
+pub fn find_conflict(storage: &dyn Storage, title_id: u64, container: Container, base_revision: Option<u64>, files: &[ClientFileInfo]) -> io::Result<Option<ConflictResponse>> {
+    let Some(base_revision) = base_revision else { return Ok(None) };
+
+    let server = storage.latest_revision(title_id, container)?;
+    if server.as_ref().is_some_and(|revision| revision.id == base_revision) {
+        return Ok(None)
+    }
+
+    Ok(Some(ConflictResponse { base_revision, server: server.map(Box::new), client: files.to_vec() }))
+}
+
+impl ConflictResponse {
+    pub fn message(&self) -> String {
+        match &self.server {
+            Some(revision) => format!("The server has moved on to revision {} by {}", revision.id, revision.uploader),
+            None => format!("Revision {} isn't the newest revision on the server", self.base_revision)
+        }
+    }
+}
@@ -1,38 +1,87 @@
-use fs_extra::dir;
-use rocket::{State, http::Status};
+use rocket::State;
 use uuid::Uuid;
 
-use crate::{config::Config, versions::v1::ticket::{TicketType, Tickets, clear_ticket_path, copy_dir_all, ticket_path}};
+use crate::{config::Config, devices::Device, error::ApiError, versions::v1::{Synced, history::ConflictRevision, locks::TitleLocks, storage::{ManifestMismatch, Store, blocking}, ticket::{TicketType, Tickets, delete_ticket}, upload::conflict::find_conflict}};
 
+// an incomplete upload isn't committed and keeps its ticket, so the client can send what's missing and end it again
+// an upload that conflicts with one committed since it began is dropped, the client has to start over
+// unless it's forced, then the revision it replaces is kept as a conflict revision
+// the committed revision is sent back, so the client can use it as the base of its next upload
 #[put("/v1/upload/<ticket>/end")]
-pub fn upload_end(tickets: &State<Tickets>, config: &State<Config>, ticket: &str) -> Result<Status, Status> {
-    let uuid = Uuid::try_parse(&ticket).map_err(|_| Status::Forbidden)?;
-    let mut ticket_map = tickets.lock().map_err(|_| Status::InternalServerError)?;
+pub async fn upload_end(device: Device, tickets: &State<Tickets>, locks: &State<TitleLocks>, config: &State<Config>, storage: &State<Store>, ticket: &str) -> Result<Synced, ApiError> {
+    let uuid = Uuid::try_parse(ticket).map_err(|_| ApiError::MalformedTicket(ticket.to_string()))?;
 
-    let Some(ticket) = ticket_map.get(&uuid).cloned() else { return Err(Status::BadRequest) };    
-    if ticket.kind != TicketType::UPLOAD {
-        return Err(Status::BadRequest)
-    }
+    // claimed by removing it, so the upload can't be ended twice
+    let ticket = {
+        let mut ticket_map = tickets.lock()?;
 
-    ticket_map.remove(&ticket.id);
-    if !config.data_directory().exists() {
-        dir::create_all(config.data_directory(), false).expect("Failed to create data directory");
-    }
-    
-    let title_path = config.data_directory().join(format!("{:X}", ticket.title_id));
-    let container_path = title_path.join(ticket.container.to_string().to_lowercase());
+        let Some(ticket) = ticket_map.get(&uuid).cloned() else { return Err(ApiError::UnknownTicket(ticket.to_string())) };
+        if ticket.kind != TicketType::UPLOAD {
+            return Err(ApiError::WrongTicketType(ticket.id.hyphenated().to_string()))
+        }
+
+        let missing = ticket.missing_files();
+        let mismatched = ticket.mismatched_files();
+        if !missing.is_empty() || !mismatched.is_empty() {
+            return Err(ApiError::Incomplete(missing, mismatched))
+        }
+
+        ticket_map.remove(&uuid);
+        ticket
+    };
+
+    // held until the commit is done, so nothing can be committed between the check and the commit
+    let _title = locks.write(ticket.title_id, ticket.container).await;
+    let (title_id, container, base_revision, client_files) = (ticket.title_id, ticket.container, ticket.revision, ticket.manifest.clone().unwrap_or_default());
+    let conflict = match blocking(storage, move |storage| find_conflict(storage, title_id, container, base_revision, &client_files)).await {
+        Ok(conflict) => conflict,
+        Err(err) => {
+            // put back so the client can end it again
+            if let Ok(mut ticket_map) = tickets.lock() {
+                ticket_map.insert(ticket.id, ticket);
+            }
+
+            return Err(ApiError::Internal(format!("Failed to check upload for {:X} for conflicts: {err}", title_id)))
+        }
+    };
 
-    if !title_path.exists() {
-        dir::create_all(&title_path, false).expect("Failed to create titles permanent path");
+    let committed = match &conflict {
+        Some(_) if !ticket.force => storage.abort_transaction(ticket.id).map(|_| None),
+        _ => {
+            let (ticket, uploader) = (ticket.clone(), device.name.clone());
+            blocking(storage, move |storage| storage.commit_transaction(&ticket, &uploader)).await.map(Some)
+        }
+    };
+
+    if delete_ticket(config, ticket.id).is_err() {
+        println!("Failed to delete ticket {}", ticket.id.hyphenated());
     }
-    else if !container_path.exists() {
-        dir::remove(&container_path).expect("Failed to remove containers old permanent path");
+
+    let committed = match committed {
+        Ok(committed) => committed,
+        Err(err) => {
+            if storage.abort_transaction(ticket.id).is_err() {
+                println!("Failed to clear ticket path {}", ticket.id.hyphenated());
+            }
+
+            if let Some(ManifestMismatch(path)) = err.get_ref().and_then(|inner| inner.downcast_ref::<ManifestMismatch>()) {
+                return Err(ApiError::ManifestMismatch(path.clone()))
+            }
+
+            return Err(ApiError::Internal(format!("Failed to commit upload for {:X}: {err}", ticket.title_id)))
+        }
+    };
+
+    let Some(conflict) = conflict else { return Ok(Synced(committed.map(|revision| revision.id))) };
+    if !ticket.force {
+        return Err(ApiError::Conflict(Box::new(conflict)))
     }
 
-    copy_dir_all(ticket_path(ticket.id), &container_path).expect("Failed to copy staging path to title path");
-    if clear_ticket_path(ticket.id).is_err() {
-        println!("Failed to clear ticket path {}", ticket.id.hyphenated());
+    if let Some(server) = conflict.server {
+        if let Err(err) = storage.add_conflict(ticket.title_id, ticket.container, ConflictRevision::new(ticket.container, &server, &device.name)) {
+            println!("Failed to keep revision {} of {:X} as a conflict: {err}", server.id, ticket.title_id);
+        }
     }
-    
-    Ok(Status::NoContent)
-}
\ No newline at end of file
+
+    Ok(Synced(committed.map(|revision| revision.id)))
+}
@@ -1,28 +1,52 @@
-use std::fs;
-
-use rocket::{State, http::Status};
+use rocket::{State, data::Data, http::Status};
 use uuid::Uuid;
-use crate::{v1::ticket::Tickets, versions::v1::ticket::{TicketType, ticket_path}};
+use crate::{config::Config, devices::Device, error::ApiError, v1::ticket::Tickets, versions::v1::{client_path::ClientPath, file_info::{EntryKind, ServerFileInfo}, storage::Store, ticket::{TicketType, save_ticket}, transfer::open_body}};
 
 #[put("/v1/upload/<ticket>/file?<path>", format = "application/octet-stream", data = "<data>")]
-pub fn upload_file(tickets: &State<Tickets>, ticket: &str, path: &str, data: Vec<u8>) -> Result<Status, Status> {
-    let uuid = Uuid::try_parse(&ticket).map_err(|_| Status::Forbidden)?;
-    let ticket_map = tickets.lock().map_err(|_| Status::InternalServerError)?;
+pub async fn upload_file(_device: Device, tickets: &State<Tickets>, config: &State<Config>, storage: &State<Store>, ticket: &str, path: &str, data: Data<'_>) -> Result<Status, ApiError> {
+    let uuid = Uuid::try_parse(ticket).map_err(|_| ApiError::MalformedTicket(ticket.to_string()))?;
+    let client_path = ClientPath::parse(path).map_err(|err| ApiError::InvalidPath(path.to_string(), err))?;
+
+    let (path, algorithm) = {
+        let mut ticket_map = tickets.lock()?;
+
+        let Some(ticket) = ticket_map.get_mut(&uuid) else { return Err(ApiError::UnknownTicket(ticket.to_string())) };
+        if ticket.kind != TicketType::UPLOAD {
+            return Err(ApiError::WrongTicketType(ticket.id.hyphenated().to_string()))
+        }
+
+        ticket.touch();
+        // stored as the manifest spells it, whatever case the client used here
+        let Some(path) = ticket.manifest_path(&client_path) else { return Err(ApiError::NotRequested(client_path.to_string())) };
+        let name = path.to_string();
 
-    let Some(ticket) = ticket_map.get(&uuid).cloned() else { return Err(Status::BadRequest) };    
-    if ticket.kind != TicketType::UPLOAD {
-        return Err(Status::BadRequest)
+        // a whole file upload replaces any chunked upload of the same path
+        ticket.set_partial_offset(&name, 0);
+        (path, ticket.algorithm)
+    };
+
+    let name = path.to_string();
+
+    // the body is streamed to an unfinished copy first, so a dropped connection never leaves a cut off file in the upload
+    let received = storage.write_file(uuid, &path, 0, &mut open_body(data, config.file_limit()), config.file_limit(), algorithm).await?;
+    if !received.complete {
+        let _ = storage.discard_file(uuid, &path);
+        return Err(ApiError::FileTooLarge(name))
     }
 
-    let base_path = ticket_path(uuid);
-    let file_path = base_path.join(path.strip_prefix("/").unwrap_or(path));
-    if !file_path.starts_with(base_path) {
-        return Err(Status::BadRequest)
+    let mut ticket_map = tickets.lock()?;
+    let Some(ticket) = ticket_map.get_mut(&uuid) else { return Err(ApiError::UnknownTicket(uuid.hyphenated().to_string())) };
+
+    if !ticket.matches_manifest(&name, received.size, &received.hash) {
+        let _ = storage.discard_file(uuid, &path);
+        return Err(ApiError::ManifestMismatch(name))
     }
 
-    let created = !file_path.exists();
-    if fs::write(file_path, data).is_err() {
-        return Err(Status::InternalServerError)
+    let created = storage.finish_file(uuid, &path)?;
+
+    ticket.receive(ServerFileInfo { path: name.clone(), size: received.size, hash: received.hash, kind: EntryKind::FILE, mtime: None });
+    if save_ticket(config, ticket).is_err() {
+        println!("Failed to save ticket {}", ticket.id.hyphenated());
     }
 
     Ok(
@@ -1,4 +1,7 @@
 pub mod begin;
 pub mod file;
+pub mod chunk;
 pub mod end;
-pub mod cancel;
\ No newline at end of file
+pub mod cancel;
+pub mod conflict;
+pub mod status;
\ No newline at end of file
@@ -0,0 +1,28 @@
+use rocket::{State, serde::json::Json};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{devices::Device, error::ApiError, versions::v1::{file_info::ServerFileInfo, ticket::{PartialFile, TicketType, Tickets}}};
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct StatusResponse {
+    ticket: String,
+    files: Vec<String>,
+    received: Vec<ServerFileInfo>,
+    partial: Vec<PartialFile>
+}
+
+// lets a client resume an upload, files lists what still has to be sent
+#[get("/v1/upload/<ticket>")]
+pub fn upload_status(_device: Device, tickets: &State<Tickets>, ticket: &str) -> Result<Json<StatusResponse>, ApiError> {
+    let uuid = Uuid::try_parse(ticket).map_err(|_| ApiError::MalformedTicket(ticket.to_string()))?;
+    let mut ticket_map = tickets.lock()?;
+
+    let Some(ticket) = ticket_map.get_mut(&uuid) else { return Err(ApiError::UnknownTicket(ticket.to_string())) };
+    if ticket.kind != TicketType::UPLOAD {
+        return Err(ApiError::WrongTicketType(ticket.id.hyphenated().to_string()))
+    }
+
+    ticket.touch();
+    Ok(Json(StatusResponse { ticket: ticket.id.hyphenated().to_string(), files: ticket.missing_files(), received: ticket.received_files.clone(), partial: ticket.partial_files.clone() }))
+}